@@ -1,13 +1,174 @@
+use std::{fmt, path::PathBuf};
+
+use num_bigint::BigInt;
+
+use crate::token::token::Span;
+
+/// Index of a variable in the resolver's symbol table.
+pub type BindingId = usize;
+
 #[derive(Debug, PartialEq)]
-pub struct Identifier(pub String);
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+    /// The variable this names, filled in by name resolution. Always `None`
+    /// for function, struct and field names.
+    pub binding: Option<BindingId>,
+}
 
 #[derive(Debug, PartialEq)]
 pub enum Statement {
-    Let(Identifier, Expression),
-    Return(Expression),
-    Ifelse(Expression, Block, Block),
-    Assert(Expression),
-    Expression(Expression),
+    /// `let pattern = value;` or `let pattern: ty = value;`
+    Let {
+        pattern: Pattern,
+        mutable: bool,
+        ty: Option<Type>,
+        value: Expression,
+        span: Span,
+    },
+    /// `target = value` or, with `op`, `target op= value`
+    Assign {
+        target: Identifier,
+        op: Option<BinaryOperator>,
+        value: Expression,
+        span: Span,
+    },
+    Return(Expression, Span),
+    Ifelse(Expression, Block, Block, Span),
+    /// `assert cond;` or `assert(cond, "message");`
+    Assert(Expression, Option<String>, Span),
+    /// `commit(value);` appends `value` to the public output journal.
+    Commit(Expression, Span),
+    Expression(Expression, Span),
+    Function(Function),
+    Struct(Struct),
+    /// `const NAME = value;`, where `value` must be a compile-time constant
+    Const {
+        name: Identifier,
+        value: Expression,
+        span: Span,
+    },
+    /// `import "path";`. Loading replaces it with the imported `Module`.
+    Import {
+        path: String,
+        span: Span,
+    },
+    Module(Module),
+    /// `public name: ty;` or `private name: ty;`
+    Input {
+        visibility: Visibility,
+        name: Identifier,
+        ty: Type,
+        span: Span,
+    },
+    /// `for var in start..end { body }`, with `end` exclusive
+    For {
+        var: Identifier,
+        start: Expression,
+        end: Expression,
+        body: Block,
+        span: Span,
+    },
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Let { span, .. }
+            | Statement::Assign { span, .. }
+            | Statement::For { span, .. }
+            | Statement::Input { span, .. }
+            | Statement::Const { span, .. }
+            | Statement::Import { span, .. }
+            | Statement::Return(_, span)
+            | Statement::Ifelse(_, _, _, span)
+            | Statement::Assert(_, _, span)
+            | Statement::Commit(_, span)
+            | Statement::Expression(_, span) => *span,
+            Statement::Function(function) => function.span,
+            Statement::Struct(declaration) => declaration.span,
+            Statement::Module(module) => module.span,
+        }
+    }
+}
+
+/// `function name(params) { body }`
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: Identifier,
+    pub params: Vec<Parameter>,
+    pub body: Block,
+    pub span: Span,
+}
+
+/// An imported file. Its functions, structs and constants are `name::item` in
+/// the importing file, where `name` is the file name without its extension.
+#[derive(Debug, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub program: Program,
+    /// The `import` statement
+    pub span: Span,
+}
+
+/// Whether an input is part of the public statement or only known to the prover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    Public,
+    Private,
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Visibility::Public => write!(f, "public"),
+            Visibility::Private => write!(f, "private"),
+        }
+    }
+}
+
+/// The left-hand side of a `let`: `x` or `(a, (b, c))`
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    Identifier(Identifier),
+    Tuple(Vec<Pattern>, Span),
+}
+
+/// `struct Name { field: ty, ... }`
+#[derive(Debug, PartialEq)]
+pub struct Struct {
+    pub name: Identifier,
+    pub fields: Vec<StructField>,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StructField {
+    pub name: Identifier,
+    pub ty: Type,
+}
+
+/// `name` or `name: ty`
+#[derive(Debug, PartialEq)]
+pub struct Parameter {
+    pub name: Identifier,
+    pub ty: Option<Type>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Type {
+    Field,
+    Bool,
+    U8,
+    U32,
+    U64,
+    /// `[ty; len]`, where `len` must be a compile-time constant
+    Array(Box<Type>, Box<Expression>),
+    /// `(a, b)`
+    Tuple(Vec<Type>),
+    /// A user-defined struct
+    Named(Identifier),
 }
 
 #[derive(Debug, PartialEq)]
@@ -16,9 +177,41 @@ pub struct Block(pub Vec<Statement>);
 #[derive(Debug, PartialEq)]
 pub enum Expression {
     Identifier(Identifier),
-    Literal(Literal),
-    BinaryOperation(BinaryOperator, Box<Expression>, Box<Expression>),
-    UnaryOperation(UnaryOperator, Box<Expression>),
+    Literal(Literal, Span),
+    BinaryOperation(BinaryOperator, Box<Expression>, Box<Expression>, Span),
+    UnaryOperation(UnaryOperator, Box<Expression>, Span),
+    Call(Identifier, Vec<Expression>, Span),
+    /// `if c { a } else { b }`. Each block's value is its last statement, which
+    /// must be an expression.
+    If(Box<Expression>, Block, Block, Span),
+    /// `[a, b, c]`
+    Array(Vec<Expression>, Span),
+    /// `array[index]`
+    Index(Box<Expression>, Box<Expression>, Span),
+    /// `(a, b)`; `()` is the empty tuple
+    Tuple(Vec<Expression>, Span),
+    /// `Name { field: value, ... }`
+    Struct(Identifier, Vec<(Identifier, Expression)>, Span),
+    /// `value.field`
+    Field(Box<Expression>, Identifier, Span),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Identifier(identifier) => identifier.span,
+            Expression::Literal(_, span)
+            | Expression::BinaryOperation(_, _, _, span)
+            | Expression::UnaryOperation(_, _, span)
+            | Expression::Call(_, _, span)
+            | Expression::If(_, _, _, span)
+            | Expression::Array(_, span)
+            | Expression::Index(_, _, span)
+            | Expression::Tuple(_, span)
+            | Expression::Struct(_, _, span)
+            | Expression::Field(_, _, span) => *span,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -33,18 +226,62 @@ pub enum BinaryOperator {
     Minus,
     Multiply,
     Divide,
+    Modulo,
     Equal,
     NotEqual,
     LessThan,
     GreaterThan,
+    LessEqual,
+    GreaterEqual,
+    /// Logical `&&`. Both sides are always evaluated.
     And,
+    /// Logical `||`. Both sides are always evaluated.
     Or,
     Xor,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOperator::Minus => write!(f, "-"),
+            UnaryOperator::Not => write!(f, "!"),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::And => "&&",
+            BinaryOperator::Or => "||",
+            BinaryOperator::Xor => "^",
+            BinaryOperator::BitAnd => "&",
+            BinaryOperator::BitOr => "|",
+            BinaryOperator::ShiftLeft => "<<",
+            BinaryOperator::ShiftRight => ">>",
+        };
+        write!(f, "{}", symbol)
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Literal {
-    Int(i64),
+    Int(BigInt),
     Bool(bool),
 }
 
@@ -53,10 +290,17 @@ pub type Program = Vec<Statement>;
 #[derive(PartialEq, PartialOrd)]
 pub enum Precedence {
     Lowest,
+    LogicalOr,   // ||
+    LogicalAnd,  // &&
     Equals,      // ==
     LessGreater, // > or <
+    BitOr,       // |
+    BitXor,      // ^
+    BitAnd,      // &
+    Shift,       // << or >>
     Sum,         // +
     Product,     // *
     Prefix,      // -X or !X
     Call,        // f(X)
+    Index,       // a[X]
 }
@@ -17,11 +17,11 @@ fn main() {
 
                 loop {
                     let tok = lexer.next_token();
-                    if tok == Token::Eof {
+                    if tok.token == Token::Eof {
                         break;
                     }
 
-                    println!("{:?}", tok);
+                    println!("{}: {:?}", tok.span, tok.token);
                 }
             }
             Err(ReadlineError::Interrupted) => {
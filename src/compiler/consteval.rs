@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+
+use super::CompileError;
+use crate::ast::ast::{
+    self, BinaryOperator, BindingId, Expression, Literal, Statement, UnaryOperator,
+};
+use crate::field::{BabyBear, Bn254, Field, Goldilocks};
+
+/// Largest shift amount allowed in a constant expression. Field elements are
+/// well under this, and it keeps `1 << n` from allocating without bound.
+pub const MAX_CONSTANT_SHIFT: u32 = 256;
+
+/// Values of the program's `const` declarations, by binding id.
+pub type Constants = HashMap<BindingId, BigInt>;
+
+/// Evaluates every top-level `const` in declaration order. A constant can only
+/// use the ones declared before it, so there are no cycles.
+pub fn evaluate_constants(program: &ast::Program) -> Result<Constants, CompileError> {
+    let mut constants = Constants::new();
+    for statement in program {
+        if let Statement::Const { name, value, .. } = statement {
+            let value = evaluate(value, &constants)?;
+            if let Some(id) = name.binding {
+                constants.insert(id, value);
+            }
+        }
+    }
+    Ok(constants)
+}
+
+/// Folds `expression` to an integer. Only integer literals, constants and
+/// arithmetic on them are allowed.
+///
+/// Arithmetic is exact, and the VM reduces the result into its field when it
+/// is pushed. `+`, `-`, `*` and `<<` agree with that in every field. `/` has
+/// to divide exactly, by a divisor that no supported modulus divides, so it
+/// is never zero in the field. `%`, `>>` and the bitwise operators act on an
+/// element's representative, so their operands have to be their own
+/// representative in every supported field: in `0..p` for the smallest `p`.
+/// The VM rejects shift amounts at or above the bit size of its modulus, so
+/// shifts by that much for the smallest `p` are field dependent too.
+pub fn evaluate(expression: &Expression, constants: &Constants) -> Result<BigInt, CompileError> {
+    use BinaryOperator::*;
+
+    let span = expression.span();
+    match expression {
+        Expression::Literal(Literal::Int(value), _) => Ok(value.clone()),
+        Expression::Identifier(identifier) => identifier
+            .binding
+            .and_then(|id| constants.get(&id))
+            .cloned()
+            .ok_or(CompileError::NotConstant(span)),
+        Expression::UnaryOperation(UnaryOperator::Minus, operand, _) => {
+            Ok(-evaluate(operand, constants)?)
+        }
+        Expression::BinaryOperation(op, lhs, rhs, _) => {
+            let lhs = evaluate(lhs, constants)?;
+            let rhs = evaluate(rhs, constants)?;
+            // The supported field with the smallest modulus
+            let smallest = BabyBear;
+            let reduced = |value: &BigInt| smallest.contains(value);
+            if matches!(op, Modulo | ShiftRight | Xor | BitAnd | BitOr)
+                && !(reduced(&lhs) && reduced(&rhs))
+            {
+                return Err(CompileError::FieldDependentConstant(span));
+            }
+            let moduli = [Bn254.modulus(), Goldilocks.modulus(), smallest.modulus()];
+            if matches!(op, Divide)
+                && !rhs.is_zero()
+                && moduli.iter().any(|&modulus| (&rhs % modulus).is_zero())
+            {
+                return Err(CompileError::FieldDependentConstant(span));
+            }
+            match op {
+                Plus => Ok(lhs + rhs),
+                Minus => Ok(lhs - rhs),
+                Multiply => Ok(lhs * rhs),
+                Divide | Modulo if rhs.is_zero() => Err(CompileError::ConstantDivisionByZero(span)),
+                Divide if !(&lhs % &rhs).is_zero() => {
+                    Err(CompileError::FieldDependentConstant(span))
+                }
+                Divide => Ok(lhs / rhs),
+                Modulo => Ok(lhs % rhs),
+                Xor => Ok(lhs ^ rhs),
+                BitAnd => Ok(lhs & rhs),
+                BitOr => Ok(lhs | rhs),
+                ShiftLeft | ShiftRight => {
+                    let amount = rhs
+                        .to_u32()
+                        .filter(|amount| *amount <= MAX_CONSTANT_SHIFT)
+                        .ok_or(CompileError::InvalidConstantShift { amount: rhs, span })?;
+                    if u64::from(amount) >= smallest.modulus().bits() {
+                        return Err(CompileError::FieldDependentConstant(span));
+                    }
+                    Ok(match op {
+                        ShiftLeft => lhs << amount,
+                        _ => lhs >> amount,
+                    })
+                }
+                Equal | NotEqual | LessThan | GreaterThan | LessEqual | GreaterEqual | And | Or => {
+                    Err(CompileError::NotConstant(span))
+                }
+            }
+        }
+        _ => Err(CompileError::NotConstant(span)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, evaluate_constants};
+    use crate::ast::ast::Statement;
+    use num_bigint::BigInt;
+
+    use crate::compiler::{compile_source, parse_source, CompileError};
+    use crate::field::BabyBear;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_constant_folding() {
+        let input = "
+        const DEPTH = 4;
+        const LEAVES = 1 << DEPTH;
+        const ROUNDS = (LEAVES * 3 - 3) / 5 % 7;
+        let x = 1;
+        x + DEPTH;";
+        let program = parse_source(input);
+        let constants = evaluate_constants(&program).unwrap();
+
+        let value = |i: usize| match &program[i] {
+            Statement::Const { value, .. } => evaluate(value, &constants),
+            Statement::Expression(value, _) => evaluate(value, &constants),
+            _ => unreachable!(),
+        };
+        assert_eq!(value(1), Ok(16.into()));
+        assert_eq!(value(2), Ok(2.into()));
+        assert!(matches!(value(4), Err(CompileError::NotConstant(_))));
+
+        let error = |input| evaluate_constants(&parse_source(input)).unwrap_err();
+        assert!(matches!(
+            error("const A = 1 / (2 - 2);"),
+            CompileError::ConstantDivisionByZero(_)
+        ));
+        assert!(matches!(
+            error("const A = 1 << 1000;"),
+            CompileError::InvalidConstantShift { .. }
+        ));
+        assert!(matches!(
+            error("const A = 7 / 2;"),
+            CompileError::FieldDependentConstant(_)
+        ));
+        assert!(matches!(
+            error("const A = (0 - 1) % 2;"),
+            CompileError::FieldDependentConstant(_)
+        ));
+        assert!(matches!(
+            error("const A = 1 < 2;"),
+            CompileError::NotConstant(_)
+        ));
+        for input in [
+            "const A = 4026531842 % 5;",
+            "const A = (1 << 40) >> 40;",
+            "const A = 1 << 31;",
+            "const A = 1 / 2013265921;",
+        ] {
+            assert!(matches!(
+                error(input),
+                CompileError::FieldDependentConstant(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_folding_matches_field() {
+        // Each expression is folded as a constant and also computed by the VM.
+        for expression in [
+            "(1 << 20) * (1 << 20) / (1 << 18) % 5",
+            "12345 ^ 678 | 9 & 2013265920",
+            "(7 * 3 - 1) >> 2",
+            "(1 << 30) * (1 << 10) * 3 - 5",
+        ] {
+            let input = format!("const A = {expression}; let b = {expression}; return (A, b);");
+            let mut vm = VM::new(compile_source(&input)).with_field(BabyBear);
+            vm.execute().unwrap();
+            assert_eq!(vm.journal[0], vm.journal[1], "{}", expression);
+        }
+
+        // Exact folding would give 2 here.
+        let mut vm = VM::new(compile_source("return 4026531842 % 5;")).with_field(BabyBear);
+        vm.execute().unwrap();
+        assert_eq!(vm.journal, vec![BigInt::from(0)]);
+    }
+}
@@ -0,0 +1,1455 @@
+use std::collections::{BTreeMap, HashMap};
+
+pub mod consteval;
+pub mod resolve;
+pub mod typecheck;
+
+use crate::{
+    ast::ast::{self, BinaryOperator, BindingId, Expression, Literal, Statement, UnaryOperator},
+    ir::{
+        Block, BlockExit, BlockId, Constant, FunctionDefinition, Input, Instruction, JumpArg,
+        Operand, Program,
+    },
+    token::token::Span,
+};
+use consteval::Constants;
+use num_bigint::BigInt;
+use num_traits::{One, ToPrimitive, Zero};
+use thiserror::Error;
+use typecheck::{Ty, Types};
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum CompileError {
+    #[error("{1}: undefined variable `{0}`")]
+    UndefinedVariable(String, Span),
+    #[error("{1}: cannot assign to immutable variable `{0}`")]
+    AssignToImmutable(String, Span),
+    #[error("{1}: undefined function `{0}`")]
+    UndefinedFunction(String, Span),
+    #[error("{1}: function `{0}` is already defined")]
+    DuplicateFunction(String, Span),
+    #[error("{1}: function `{0}` must be declared at the top level")]
+    NestedFunction(String, Span),
+    #[error("{0}: loop bound must be a compile-time constant")]
+    NonConstantLoopBound(Span),
+    #[error(
+        "{span}: loop unrolls to {iterations} iterations counting enclosing loops, more than the limit of {}",
+        MAX_UNROLLED_ITERATIONS
+    )]
+    UnrollLimit { iterations: BigInt, span: Span },
+    #[error("{0}: if-expression branch must end with an expression")]
+    MissingBranchValue(Span),
+    #[error("{1}: struct `{0}` is already defined")]
+    DuplicateStruct(String, Span),
+    #[error("{1}: struct `{0}` must be declared at the top level")]
+    NestedStruct(String, Span),
+    #[error("{1}: input `{0}` must be declared at the top level")]
+    NestedInput(String, Span),
+    #[error("{1}: `{0}` must be loaded with `loader::load` before compiling")]
+    UnloadedImport(String, Span),
+    #[error("{1}: `{0}` must be imported at the top level")]
+    NestedImport(String, Span),
+    #[error("{1}: constant `{0}` must be declared at the top level")]
+    NestedConst(String, Span),
+    #[error("{0}: expression is not a compile-time constant")]
+    NotConstant(Span),
+    #[error("{0}: division by zero in constant expression")]
+    ConstantDivisionByZero(Span),
+    #[error("{0}: the value of this constant expression depends on the field")]
+    FieldDependentConstant(Span),
+    #[error("{span}: invalid shift amount {amount} in constant expression")]
+    InvalidConstantShift { amount: BigInt, span: Span },
+    #[error("{1}: undefined struct `{0}`")]
+    UndefinedStruct(String, Span),
+    #[error("{1}: field `{0}` is given more than once")]
+    DuplicateField(String, Span),
+    #[error("{span}: struct `{name}` has no field `{field}`")]
+    UnknownField {
+        name: String,
+        field: String,
+        span: Span,
+    },
+    #[error("{span}: missing field `{field}` of struct `{name}`")]
+    MissingField {
+        name: String,
+        field: String,
+        span: Span,
+    },
+    #[error("{span}: `{name}` is already defined in this scope at {previous}")]
+    DuplicateDefinition {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    #[error("{0}: array length must be a compile-time constant")]
+    NonConstantLength(Span),
+    #[error("{1}: cannot infer the return type of recursive function `{0}`")]
+    RecursiveFunction(String, Span),
+    #[error("{1}: function `{0}` does not return a value on every path")]
+    MissingReturn(String, Span),
+    #[error("{span}: expected `{expected}`, found `{found}`")]
+    TypeMismatch { expected: Ty, found: Ty, span: Span },
+    #[error("{span}: a pattern of {len} elements cannot bind a value of type `{found}`")]
+    PatternMismatch { len: usize, found: Ty, span: Span },
+    #[error("{span}: literal {value} does not fit in `{ty}`")]
+    LiteralOutOfRange { value: BigInt, ty: Ty, span: Span },
+    #[error("{span}: operator `{op}` cannot be applied to `{ty}`")]
+    InvalidOperand { op: String, ty: Ty, span: Span },
+    #[error("{0}: cannot infer the element type of an empty array")]
+    EmptyArray(Span),
+    #[error("{span}: cannot index into a value of type `{found}`")]
+    NotAnArray { found: Ty, span: Span },
+    #[error("{span}: array index must be an integer, found `{ty}`")]
+    InvalidIndex { ty: Ty, span: Span },
+    #[error("{span}: index {index} is out of bounds for an array of length {len}")]
+    IndexOutOfBounds {
+        index: BigInt,
+        len: usize,
+        span: Span,
+    },
+    #[error("{span}: `{found}` has no fields")]
+    NotAStruct { found: Ty, span: Span },
+    #[error("{span}: `{name}` takes {expected} arguments but {found} were given")]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+}
+
+/// Most copies of a loop body that unrolling may produce, counting the
+/// iterations of enclosing loops: nested loops multiply.
+pub const MAX_UNROLLED_ITERATIONS: usize = 1 << 16;
+
+/// Lowers a parsed program into stack IR. Top-level functions become
+/// `Program::functions`; everything else is the program body, starting at block 0.
+///
+/// `program` must have been through `resolve::resolve`; variables are looked up
+/// by binding id, so unresolved ones are reported as undefined.
+pub fn compile(program: &ast::Program) -> Result<Program, CompileError> {
+    let mut globals = Globals::default();
+    for statement in program {
+        match statement {
+            Statement::Function(function) => {
+                let name = &function.name;
+                if globals
+                    .arities
+                    .insert(name.name.clone(), function.params.len())
+                    .is_some()
+                {
+                    return Err(CompileError::DuplicateFunction(
+                        name.name.clone(),
+                        name.span,
+                    ));
+                }
+            }
+            Statement::Struct(declaration) => {
+                let name = &declaration.name;
+                let mut fields: Vec<String> = Vec::new();
+                for field in &declaration.fields {
+                    if fields.contains(&field.name.name) {
+                        return Err(CompileError::DuplicateField(
+                            field.name.name.clone(),
+                            field.name.span,
+                        ));
+                    }
+                    fields.push(field.name.name.clone());
+                }
+                if globals.structs.insert(name.name.clone(), fields).is_some() {
+                    return Err(CompileError::DuplicateStruct(name.name.clone(), name.span));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    globals.types = typecheck::check(program)?;
+
+    let mut functions = BTreeMap::new();
+    for statement in program {
+        if let Statement::Function(function) = statement {
+            let mut compiler = Compiler::new(&globals);
+            compiler.compile_function(function)?;
+            functions.insert(
+                function.name.name.clone(),
+                FunctionDefinition {
+                    params: function.params.len(),
+                    blocks: compiler.blocks,
+                },
+            );
+        }
+    }
+
+    let mut compiler = Compiler::new(&globals);
+    compiler.main = true;
+    let mut inputs = vec![];
+    for statement in program {
+        match statement {
+            Statement::Function(_) | Statement::Struct(_) | Statement::Const { .. } => {}
+            Statement::Input {
+                visibility, name, ..
+            } => {
+                compiler.compile_input(*visibility, name);
+                inputs.push(Input {
+                    name: name.name.clone(),
+                    visibility: *visibility,
+                });
+            }
+            statement => compiler.compile_statement(statement)?,
+        }
+    }
+    compiler.terminate(BlockExit::Return { value: None });
+
+    Ok(Program {
+        blocks: compiler.blocks,
+        functions,
+        inputs,
+    })
+}
+
+/// Parses and resolves `input` for tests, panicking on errors.
+#[cfg(test)]
+pub(crate) fn parse_source(input: &str) -> ast::Program {
+    let mut program = crate::parser::parser::Parser::new(crate::lexer::lexer::Lexer::new(input))
+        .parse_program()
+        .unwrap();
+    resolve::resolve(&mut program).unwrap();
+    program
+}
+
+/// Parses, resolves and compiles `input` for tests, panicking on errors.
+#[cfg(test)]
+pub(crate) fn compile_source(input: &str) -> Program {
+    compile(&parse_source(input)).unwrap()
+}
+
+/// Top-level declarations, visible from every function.
+#[derive(Default)]
+struct Globals {
+    /// Parameter count of every function
+    arities: HashMap<String, usize>,
+    /// Field names of every struct, in declaration order
+    structs: HashMap<String, Vec<String>>,
+    types: Types,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Binding {
+    /// Address of the current version. Every assignment to a mutable variable
+    /// writes a fresh address, so each address is stored to once.
+    address: usize,
+    mutable: bool,
+}
+
+struct Compiler<'a> {
+    globals: &'a Globals,
+    blocks: Vec<Block>,
+    current: BlockId,
+    variables: HashMap<BindingId, Binding>,
+    next_address: usize,
+    /// Whether this is the top-level code rather than a function
+    main: bool,
+    /// Product of the iteration counts of the loops being unrolled
+    unrolled: BigInt,
+    /// The program's constants, plus each enclosing loop variable's value in
+    /// the iteration being unrolled
+    constants: Constants,
+    /// Whether the current block follows a `return`, so no path reaches it
+    returned: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(globals: &'a Globals) -> Self {
+        let mut compiler = Compiler {
+            globals,
+            blocks: vec![],
+            current: 0,
+            variables: HashMap::new(),
+            next_address: 0,
+            main: false,
+            unrolled: BigInt::one(),
+            constants: globals.types.constants().clone(),
+            returned: false,
+        };
+        compiler.current = compiler.new_block();
+        compiler
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block {
+            instructions: vec![],
+            exit: BlockExit::Unreachable,
+        });
+        self.blocks.len() - 1
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.blocks[self.current].instructions.push(instruction);
+    }
+
+    fn push_num(&mut self, value: impl Into<BigInt>) {
+        self.emit(Instruction::Push {
+            value: Constant::Num {
+                value: value.into(),
+            },
+        });
+    }
+
+    fn terminate(&mut self, exit: BlockExit) {
+        self.blocks[self.current].exit = exit;
+    }
+
+    /// Ends the current block with a jump to `target` and continues in `target`.
+    fn jump_to(&mut self, target: BlockId, args: Vec<Operand>) {
+        self.terminate(BlockExit::Jump { target, args });
+        self.current = target;
+    }
+
+    /// Constrains the value on top of the stack to `ty`: 0 or 1 for `bool`,
+    /// the type's range for unsigned integers.
+    fn constrain(&mut self, ty: &Ty) {
+        match ty {
+            Ty::Bool => self.emit(Instruction::RangeCheck { bits: 1 }),
+            Ty::Uint(bits) => self.emit(Instruction::RangeCheck { bits: *bits }),
+            _ => {}
+        }
+    }
+
+    /// Constrains the index on top of the stack to `0..len`: to the bits of
+    /// `len - 1`, and below `len` when that range check alone allows more.
+    fn constrain_index(&mut self, len: usize, span: Span) {
+        let bits = usize::BITS - len.saturating_sub(1).leading_zeros();
+        self.emit(Instruction::RangeCheck { bits });
+        if !len.is_power_of_two() {
+            self.emit(Instruction::Dup);
+            self.push_num(len);
+            self.emit(Instruction::BinOp {
+                op: BinaryOperator::LessThan,
+            });
+            self.emit(Instruction::Assert {
+                message: Some("index out of bounds".to_string()),
+                span,
+            });
+        }
+    }
+
+    fn compile_input(&mut self, visibility: ast::Visibility, name: &ast::Identifier) {
+        let ty = self
+            .globals
+            .types
+            .variable(name)
+            .cloned()
+            .unwrap_or(Ty::Field);
+        self.read(visibility, &ty);
+        let address = self.declare(name, false);
+        self.push_num(address);
+        self.emit(Instruction::Store);
+    }
+
+    /// Reads a value of type `ty` from an input tape, one tape entry per scalar.
+    /// Scalars are constrained to their type, since the prover chooses them.
+    fn read(&mut self, visibility: ast::Visibility, ty: &Ty) {
+        match ty {
+            Ty::Field | Ty::Bool | Ty::Uint(_) => {
+                self.emit(Instruction::Read { visibility });
+                self.constrain(ty);
+            }
+            Ty::Array(element, len) => {
+                for _ in 0..*len {
+                    self.read(visibility, element);
+                }
+                self.emit(Instruction::MakeArray { len: *len });
+            }
+            Ty::Tuple(elements) => {
+                for element in elements {
+                    self.read(visibility, element);
+                }
+                self.emit(Instruction::MakeTuple {
+                    len: elements.len(),
+                });
+            }
+            Ty::Struct(name) => {
+                let fields = self.globals.types.fields(name);
+                for (_, field) in fields {
+                    self.read(visibility, field);
+                }
+                self.emit(Instruction::MakeStruct {
+                    name: name.clone(),
+                    fields: fields.iter().map(|(field, _)| field.clone()).collect(),
+                });
+            }
+        }
+    }
+
+    fn fresh_address(&mut self) -> usize {
+        let address = self.next_address;
+        self.next_address += 1;
+        address
+    }
+
+    fn declare(&mut self, identifier: &ast::Identifier, mutable: bool) -> usize {
+        let address = self.fresh_address();
+        if let Some(id) = identifier.binding {
+            self.variables.insert(id, Binding { address, mutable });
+        }
+        address
+    }
+
+    fn lookup(&self, identifier: &ast::Identifier) -> Result<usize, CompileError> {
+        identifier
+            .binding
+            .and_then(|id| self.variables.get(&id))
+            .map(|binding| binding.address)
+            .ok_or_else(|| {
+                CompileError::UndefinedVariable(identifier.name.clone(), identifier.span)
+            })
+    }
+
+    /// Starts a new version of a mutable variable and returns its address.
+    fn assign(&mut self, identifier: &ast::Identifier) -> Result<usize, CompileError> {
+        let address = self.fresh_address();
+        let binding = identifier
+            .binding
+            .and_then(|id| self.variables.get_mut(&id))
+            .ok_or_else(|| {
+                CompileError::UndefinedVariable(identifier.name.clone(), identifier.span)
+            })?;
+        if !binding.mutable {
+            return Err(CompileError::AssignToImmutable(
+                identifier.name.clone(),
+                identifier.span,
+            ));
+        }
+        binding.address = address;
+        Ok(address)
+    }
+
+    fn compile_function(&mut self, function: &ast::Function) -> Result<(), CompileError> {
+        let addresses: Vec<usize> = function
+            .params
+            .iter()
+            .map(|param| self.declare(&param.name, false))
+            .collect();
+        // The last argument is on top of the stack. Arguments are constrained on
+        // entry, so the body can rely on its parameter types however it is called.
+        let types = self.globals.types.params(&function.name.name);
+        for (address, ty) in addresses.into_iter().zip(types).rev() {
+            self.constrain(ty);
+            self.push_num(address);
+            self.emit(Instruction::Store);
+        }
+
+        self.compile_statements(&function.body.0)?;
+
+        // Falling off the end returns unit.
+        self.terminate(BlockExit::Return {
+            value: Some(Operand::Constant(Constant::Unit)),
+        });
+        Ok(())
+    }
+
+    fn compile_statements(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
+        for statement in statements {
+            self.compile_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, block: &ast::Block) -> Result<(), CompileError> {
+        self.compile_statements(&block.0)
+    }
+
+    /// Compiles a block whose last statement is an expression and leaves its value on the stack.
+    fn compile_block_value(&mut self, block: &ast::Block, span: Span) -> Result<(), CompileError> {
+        let (value, statements) = match block.0.split_last() {
+            Some((Statement::Expression(value, _), statements)) => (value, statements),
+            _ => return Err(CompileError::MissingBranchValue(span)),
+        };
+
+        self.compile_statements(statements)?;
+        self.compile_expression(value)
+    }
+
+    /// Lowers both forms of `if`. With `value` set each branch leaves its value on
+    /// the stack for the join block; `value` is the span reported if it has none.
+    fn compile_if(
+        &mut self,
+        condition: &Expression,
+        then_block: &ast::Block,
+        else_block: &ast::Block,
+        value: Option<Span>,
+    ) -> Result<(), CompileError> {
+        let then_id = self.new_block();
+        let else_id = self.new_block();
+        let join_id = self.new_block();
+
+        self.compile_expression(condition)?;
+        self.terminate(BlockExit::ConditionalJump {
+            cond: Operand::StackValue(0),
+            then: JumpArg {
+                target: then_id,
+                args: vec![],
+            },
+            else_: JumpArg {
+                target: else_id,
+                args: vec![],
+            },
+        });
+
+        let before = self.variables.clone();
+        let returned = self.returned;
+
+        self.current = then_id;
+        self.compile_branch(then_block, value)?;
+        let then_end = self.current;
+        let then_returned = std::mem::replace(&mut self.returned, returned);
+        let mut then_variables = std::mem::replace(&mut self.variables, before.clone());
+
+        self.current = else_id;
+        self.compile_branch(else_block, value)?;
+        let else_end = self.current;
+        let else_returned = self.returned;
+        // The join is reached only through the branches that didn't return.
+        self.returned = then_returned && else_returned;
+        if else_returned && !then_returned {
+            self.variables = std::mem::take(&mut then_variables);
+        }
+
+        // Variables declared in either branch are out of scope after the join.
+        self.variables.retain(|id, _| before.contains_key(id));
+
+        // A variable assigned on either path gets one more version at the join,
+        // written by whichever branch ran. A branch that returned doesn't
+        // reach the join, so the other one's versions stand.
+        let mut merges = vec![];
+        if !then_returned && !else_returned {
+            for (id, else_binding) in &self.variables {
+                let then_address = then_variables[id].address;
+                if then_address != else_binding.address {
+                    merges.push((*id, then_address, else_binding.address));
+                }
+            }
+        }
+        merges.sort();
+
+        for (id, then_address, else_address) in merges {
+            let merged = self.fresh_address();
+            for (block, address) in [(then_end, then_address), (else_end, else_address)] {
+                self.current = block;
+                self.push_num(address);
+                self.emit(Instruction::Load);
+                self.push_num(merged);
+                self.emit(Instruction::Store);
+            }
+            if let Some(binding) = self.variables.get_mut(&id) {
+                binding.address = merged;
+            }
+        }
+
+        // The value of an `if` expression is passed to the join block.
+        let args = || match value {
+            Some(_) => vec![Operand::StackValue(0)],
+            None => vec![],
+        };
+        for (end, returned) in [(then_end, then_returned), (else_end, else_returned)] {
+            if !returned {
+                self.current = end;
+                self.jump_to(join_id, args());
+            }
+        }
+        self.current = join_id;
+        Ok(())
+    }
+
+    fn compile_branch(
+        &mut self,
+        block: &ast::Block,
+        value: Option<Span>,
+    ) -> Result<(), CompileError> {
+        match value {
+            Some(span) => self.compile_block_value(block, span),
+            None => self.compile_block(block),
+        }
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Let {
+                pattern,
+                mutable,
+                value,
+                ..
+            } => {
+                self.compile_expression(value)?;
+                self.bind(pattern, *mutable);
+            }
+            Statement::Assign {
+                target, op, value, ..
+            } => {
+                if op.is_some() {
+                    let address = self.lookup(target)?;
+                    self.push_num(address);
+                    self.emit(Instruction::Load);
+                }
+                self.compile_expression(value)?;
+                if let Some(op) = op {
+                    self.emit(Instruction::BinOp { op: op.clone() });
+                }
+                let address = self.assign(target)?;
+                self.push_num(address);
+                self.emit(Instruction::Store);
+            }
+            Statement::Return(expression, _) => {
+                self.compile_expression(expression)?;
+                // The program's result is public: it goes to the journal too.
+                if self.main {
+                    self.emit(Instruction::Dup);
+                    self.emit(Instruction::Commit);
+                }
+                self.terminate(BlockExit::Return {
+                    value: Some(Operand::StackValue(0)),
+                });
+                // Anything after a return is unreachable but still has to live in a block.
+                self.current = self.new_block();
+                self.returned = true;
+            }
+            Statement::Ifelse(condition, then_block, else_block, _) => {
+                self.compile_if(condition, then_block, else_block, None)?;
+            }
+            Statement::Assert(condition, message, span) => {
+                self.compile_expression(condition)?;
+                self.emit(Instruction::Assert {
+                    message: message.clone(),
+                    span: *span,
+                });
+            }
+            Statement::Expression(expression, _) => {
+                self.compile_expression(expression)?;
+                self.emit(Instruction::Pop);
+            }
+            Statement::Commit(expression, _) => {
+                self.compile_expression(expression)?;
+                self.emit(Instruction::Commit);
+            }
+            Statement::For {
+                var,
+                start,
+                end,
+                body,
+                span,
+            } => {
+                let start = self.loop_bound(start)?;
+                let end = self.loop_bound(end)?;
+                let iterations = (&end - &start).max(BigInt::zero()) * &self.unrolled;
+                if iterations > BigInt::from(MAX_UNROLLED_ITERATIONS) {
+                    return Err(CompileError::UnrollLimit {
+                        iterations,
+                        span: *span,
+                    });
+                }
+
+                // Unrolled: each iteration is the body with `var` bound to a constant.
+                let enclosing = std::mem::replace(&mut self.unrolled, iterations);
+                let mut i = start;
+                while i < end {
+                    if let Some(id) = var.binding {
+                        self.constants.insert(id, i.clone());
+                    }
+                    self.push_num(i.clone());
+                    let address = self.declare(var, false);
+                    self.push_num(address);
+                    self.emit(Instruction::Store);
+                    self.compile_block(body)?;
+                    i += 1;
+                }
+                self.unrolled = enclosing;
+                if let Some(id) = var.binding {
+                    self.constants.remove(&id);
+                }
+            }
+            Statement::Function(function) => {
+                return Err(CompileError::NestedFunction(
+                    function.name.name.clone(),
+                    function.name.span,
+                ));
+            }
+            Statement::Struct(declaration) => {
+                return Err(CompileError::NestedStruct(
+                    declaration.name.name.clone(),
+                    declaration.name.span,
+                ));
+            }
+            Statement::Input { name, .. } => {
+                return Err(CompileError::NestedInput(name.name.clone(), name.span));
+            }
+            Statement::Const { name, .. } => {
+                return Err(CompileError::NestedConst(name.name.clone(), name.span));
+            }
+            // Top-level modules were hoisted by resolution.
+            Statement::Import { path, span } => {
+                return Err(CompileError::NestedImport(path.clone(), *span));
+            }
+            Statement::Module(module) => {
+                return Err(CompileError::NestedImport(
+                    module.path.display().to_string(),
+                    module.span,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bounds can use the program's constants and the variables of the loops
+    /// being unrolled around them.
+    fn loop_bound(&self, expression: &Expression) -> Result<BigInt, CompileError> {
+        match consteval::evaluate(expression, &self.constants) {
+            Err(CompileError::NotConstant(_)) => {
+                Err(CompileError::NonConstantLoopBound(expression.span()))
+            }
+            result => result,
+        }
+    }
+
+    /// Stores the value on top of the stack into the variables of `pattern`.
+    fn bind(&mut self, pattern: &ast::Pattern, mutable: bool) {
+        match pattern {
+            ast::Pattern::Identifier(name) => {
+                let address = self.declare(name, mutable);
+                self.push_num(address);
+                self.emit(Instruction::Store);
+            }
+            ast::Pattern::Tuple(patterns, _) => {
+                self.emit(Instruction::Unpack {
+                    len: patterns.len(),
+                });
+                // The last element is on top of the stack.
+                for pattern in patterns.iter().rev() {
+                    self.bind(pattern, mutable);
+                }
+            }
+        }
+    }
+
+    /// Pushes the field values in declaration order, whatever order the literal
+    /// lists them in. Unknown and repeated fields were rejected by type checking.
+    fn compile_struct(
+        &mut self,
+        name: &ast::Identifier,
+        values: &[(ast::Identifier, Expression)],
+    ) -> Result<(), CompileError> {
+        let fields = self
+            .globals
+            .structs
+            .get(&name.name)
+            .ok_or_else(|| CompileError::UndefinedStruct(name.name.clone(), name.span))?;
+
+        for field in fields {
+            let (_, value) = values
+                .iter()
+                .find(|(given, _)| &given.name == field)
+                .ok_or_else(|| CompileError::MissingField {
+                    name: name.name.clone(),
+                    field: field.clone(),
+                    span: name.span,
+                })?;
+            self.compile_expression(value)?;
+        }
+        self.emit(Instruction::MakeStruct {
+            name: name.name.clone(),
+            fields: fields.clone(),
+        });
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), CompileError> {
+        match expression {
+            Expression::Identifier(identifier) => {
+                let globals = self.globals;
+                match globals.types.constant(identifier) {
+                    Some(value) => self.push_num(value.clone()),
+                    None => {
+                        let address = self.lookup(identifier)?;
+                        self.push_num(address);
+                        self.emit(Instruction::Load);
+                    }
+                }
+            }
+            Expression::Literal(Literal::Int(value), _) => self.push_num(value.clone()),
+            Expression::Literal(Literal::Bool(value), _) => self.push_num(*value as u8),
+            Expression::BinaryOperation(op, lhs, rhs, _) => {
+                self.compile_expression(lhs)?;
+                self.compile_expression(rhs)?;
+                let ty = self.globals.types.get(expression);
+                match (op, ty) {
+                    // `/` is field division, which would not round.
+                    (BinaryOperator::Divide, Some(Ty::Uint(_))) => {
+                        self.emit(Instruction::IntegerDivide)
+                    }
+                    _ => self.emit(Instruction::BinOp { op: op.clone() }),
+                }
+                // Integer results that can leave the type's range must be checked.
+                if let Some(Ty::Uint(bits)) = ty {
+                    if matches!(
+                        op,
+                        BinaryOperator::Plus
+                            | BinaryOperator::Minus
+                            | BinaryOperator::Multiply
+                            | BinaryOperator::ShiftLeft
+                    ) {
+                        self.emit(Instruction::RangeCheck { bits: *bits });
+                    }
+                }
+            }
+            Expression::UnaryOperation(op, operand, _) => {
+                self.compile_expression(operand)?;
+                match (op, self.globals.types.get(expression)) {
+                    // Bitwise complement within the type's width
+                    (UnaryOperator::Not, Some(Ty::Uint(bits))) => {
+                        self.push_num((BigInt::from(1) << *bits) - 1);
+                        self.emit(Instruction::BinOp {
+                            op: BinaryOperator::Xor,
+                        });
+                    }
+                    _ => self.emit(Instruction::UnaryOp { op: op.clone() }),
+                }
+            }
+            Expression::Call(name, args, span) => {
+                let expected =
+                    *self.globals.arities.get(&name.name).ok_or_else(|| {
+                        CompileError::UndefinedFunction(name.name.clone(), name.span)
+                    })?;
+                if expected != args.len() {
+                    return Err(CompileError::ArityMismatch {
+                        name: name.name.clone(),
+                        expected,
+                        found: args.len(),
+                        span: *span,
+                    });
+                }
+
+                for arg in args {
+                    self.compile_expression(arg)?;
+                }
+                self.emit(Instruction::Call {
+                    name: name.name.clone(),
+                });
+            }
+            Expression::If(condition, then_block, else_block, span) => {
+                self.compile_if(condition, then_block, else_block, Some(*span))?;
+            }
+            Expression::Array(elements, _) => {
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                self.emit(Instruction::MakeArray {
+                    len: elements.len(),
+                });
+            }
+            Expression::Tuple(elements, _) => {
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                self.emit(Instruction::MakeTuple {
+                    len: elements.len(),
+                });
+            }
+            Expression::Struct(name, values, _) => self.compile_struct(name, values)?,
+            Expression::Field(value, field, _) => {
+                self.compile_expression(value)?;
+                self.emit(Instruction::GetField {
+                    name: field.name.clone(),
+                });
+            }
+            Expression::Index(array, index, span) => {
+                self.compile_expression(array)?;
+                // Constant indices, loop variables included, address the
+                // element directly; anything else needs a selection over all of them.
+                match consteval::evaluate(index, &self.constants)
+                    .ok()
+                    .and_then(|index| index.to_usize())
+                {
+                    Some(index) => self.emit(Instruction::Get { index }),
+                    None => {
+                        self.compile_expression(index)?;
+                        if let Some(Ty::Array(_, len)) = self.globals.types.get(array) {
+                            self.constrain_index(*len, *span);
+                        }
+                        self.emit(Instruction::Select);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{compile, compile_source, parse_source, CompileError};
+    use crate::ast::ast::{BinaryOperator, Visibility};
+    use crate::field::{Bn254, Field};
+    use crate::ir::{Block, BlockExit, Constant, Input, Instruction, Operand};
+    use crate::vm::{VMError, Value, VM};
+    use num_bigint::BigInt;
+
+    #[test]
+    fn test_straight_line_program_runs() {
+        let program = compile_source("let x = 2; let y = x * 3; return y + 1;");
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 7.into() }]);
+    }
+
+    #[test]
+    fn test_ifelse_blocks() {
+        let program = compile_source("let x = 1; if (x == 1) { let y = x; } else { x; }");
+
+        // entry, then, else, join
+        assert_eq!(program.blocks.len(), 4);
+        assert_eq!(program.blocks[0].exit.successors(), vec![1, 2]);
+        assert_eq!(
+            program.blocks[1].exit,
+            BlockExit::Jump {
+                target: 3,
+                args: vec![]
+            }
+        );
+        assert_eq!(program.blocks[2].exit.successors(), vec![3]);
+        assert_eq!(program.blocks[3].exit, BlockExit::Return { value: None });
+    }
+
+    #[test]
+    fn test_if_expression_blocks() {
+        let program = compile_source("let y = if true { 1 } else { let z = 2; z };");
+
+        // entry, then, else, join
+        assert_eq!(program.blocks.len(), 4);
+        assert!(matches!(
+            program.blocks[0].exit,
+            BlockExit::ConditionalJump { .. }
+        ));
+        // Each branch passes its value to the join block.
+        for block in &program.blocks[1..3] {
+            assert_eq!(
+                block.exit,
+                BlockExit::Jump {
+                    target: 3,
+                    args: vec![Operand::StackValue(0)]
+                }
+            );
+        }
+        assert_eq!(
+            program.blocks[3].instructions.last(),
+            Some(&Instruction::Store)
+        );
+
+        let result = compile(&parse_source("let y = if true { 1 } else { let z = 2; };"));
+        assert!(matches!(result, Err(CompileError::MissingBranchValue(_))));
+
+        let input = "let a = false; let b = true;
+            return if a { 1 } else { if b { let c = 2; if c == 2 { c } else { 4 } } else { 5 } };";
+        let mut vm = VM::new(compile_source(input));
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 2.into() }]);
+    }
+
+    /// Addresses stored to in `block`, in order
+    fn stores(block: &Block) -> Vec<Constant> {
+        block
+            .instructions
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Instruction::Push { value }, Instruction::Store] => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_assignment_versions() {
+        let input = "let mut acc = 1; acc = acc + 1; acc += 2; acc *= 3; return acc;";
+        let program = compile_source(input);
+
+        let addresses = stores(&program.blocks[0]);
+        let unique: HashSet<_> = addresses.iter().collect();
+        assert_eq!((addresses.len(), unique.len()), (4, 4));
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 12.into() }]);
+
+        let result = compile(&parse_source("let x = 1; x = 2;"));
+        assert!(matches!(result, Err(CompileError::AssignToImmutable(..))));
+    }
+
+    #[test]
+    fn test_assignment_merges_at_join() {
+        let input =
+            "let mut x = 1; let mut y = 1; if true { x = 2; } else { y = 3; } return x + y;";
+        let program = compile_source(input);
+
+        // Both branches end by writing the merged versions of x and y.
+        let then_stores = stores(&program.blocks[1]);
+        let else_stores = stores(&program.blocks[2]);
+        assert_eq!(then_stores.len(), 3);
+        assert_eq!(then_stores[1..], else_stores[1..]);
+
+        // Only the branch taken writes them.
+        let input = "private c: bool; let mut x = 1; let mut y = 1;
+            if c { x = 2; } else { y = 3; } return x + y;";
+        let program = compile_source(input);
+        let run = |c: i64| {
+            let mut vm = VM::new(program.clone()).with_inputs(vec![], vec![c.into()]);
+            vm.execute().unwrap();
+            vm.journal
+        };
+        assert_eq!(run(1), vec![BigInt::from(3)]);
+        assert_eq!(run(0), vec![BigInt::from(4)]);
+
+        // A branch that returns has nothing to merge: the join takes the
+        // other branch's versions, and the block after the return stays empty.
+        let input = "private c: bool; let mut x = 1;
+            if c { x = 2; return x; } else { x = 3; } return x;";
+        let program = compile_source(input);
+        // entry, then, else, join, then the block after the first return
+        assert_eq!(stores(&program.blocks[2]).len(), 1);
+        assert_eq!(
+            program.blocks[2].exit,
+            BlockExit::Jump {
+                target: 3,
+                args: vec![]
+            }
+        );
+        assert!(program.blocks[4].instructions.is_empty());
+        assert_eq!(program.blocks[4].exit, BlockExit::Unreachable);
+        let run = |c: i64| {
+            let mut vm = VM::new(program.clone()).with_inputs(vec![], vec![c.into()]);
+            vm.execute().unwrap();
+            vm.journal
+        };
+        assert_eq!(run(1), vec![BigInt::from(2)]);
+        assert_eq!(run(0), vec![BigInt::from(3)]);
+    }
+
+    #[test]
+    fn test_for_loop_unrolls() {
+        let input = r#"
+        let mut acc = 0;
+        for i in 0..4 {
+            for j in 0..3 {
+                acc += i * j;
+            }
+            acc += i * 10;
+        }
+        return acc;
+        "#;
+        let program = compile_source(input);
+        // Straight-line code up to the return
+        assert!(matches!(
+            program.blocks[0].exit,
+            BlockExit::Return { value: Some(_) }
+        ));
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 78.into() }]);
+
+        // Bounds may use the variables of the enclosing loops.
+        let input = "let mut acc = 0;
+            for i in 0..4 { for j in 0..i { for k in j..i + 1 { acc += 1; } } }
+            return acc;";
+        let mut vm = VM::new(compile_source(input));
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 16.into() }]);
+    }
+
+    #[test]
+    fn test_for_loop_errors() {
+        let result = compile(&parse_source("let n = 3; for i in 0..n { }"));
+        assert!(matches!(result, Err(CompileError::NonConstantLoopBound(_))));
+
+        let result = compile(&parse_source("for i in 0..1000000 { }"));
+        assert!(matches!(result, Err(CompileError::UnrollLimit { .. })));
+
+        // Each loop is within the limit on its own, but nesting multiplies.
+        let input = "for i in 0..65536 { for j in 0..65536 { for k in 0..65536 { } } }";
+        let Err(CompileError::UnrollLimit { iterations, .. }) = compile(&parse_source(input))
+        else {
+            panic!("expected the unroll limit");
+        };
+        assert_eq!(iterations, BigInt::from(1u64 << 32));
+        assert!(compile(&parse_source("for i in 0..256 { for j in 0..256 { } }")).is_ok());
+    }
+
+    #[test]
+    fn test_function_calls() {
+        let input = r#"
+        function square(x) {
+            return x * x;
+        }
+        function sum_of_squares(a, b) {
+            let x = square(a);
+            return x + square(b);
+        }
+        return sum_of_squares(3, 4);
+        "#;
+        let program = compile_source(input);
+        assert_eq!(program.functions["sum_of_squares"].params, 2);
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 25.into() }]);
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let result = compile(&parse_source("function f(a) { return a; } f(1, 2);"));
+        assert!(matches!(
+            result,
+            Err(CompileError::ArityMismatch {
+                expected: 1,
+                found: 2,
+                ..
+            })
+        ));
+
+        let result = compile(&parse_source("g(1);"));
+        assert!(matches!(result, Err(CompileError::UndefinedFunction(..))));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let program = compile(&parse_source(
+            "function sum(v: [field; 3]) {
+                let mut acc = 0;
+                for i in 0..3 { acc += v[i]; }
+                return acc;
+            }
+            let a = [4, 5, 6];
+            return sum(a) * [7, 8][1] + a[2];",
+        ))
+        .unwrap();
+
+        let body = &program.blocks[0].instructions;
+        assert!(body.contains(&Instruction::MakeArray { len: 3 }));
+        assert!(body.contains(&Instruction::Get { index: 2 }));
+        // The loop variable is a constant in each unrolled iteration.
+        let sum = &program.functions["sum"].blocks[0].instructions;
+        for index in 0..3 {
+            assert!(sum.contains(&Instruction::Get { index }));
+        }
+        assert!(!sum.contains(&Instruction::Select));
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 126.into() }]);
+    }
+
+    #[test]
+    fn test_dynamic_index_constraints() {
+        // A power-of-two length is covered by the range check alone.
+        let at = compile_source("function at(v: [field; 2], i) { return v[i + 1]; }");
+        let instructions = &at.functions["at"].blocks[0].instructions;
+        assert!(instructions
+            .windows(2)
+            .any(|pair| pair == [Instruction::RangeCheck { bits: 1 }, Instruction::Select]));
+
+        let input = "private i: field; let v = [4, 5, 6]; return v[i];";
+        let program = compile_source(input);
+        let instructions = &program.blocks[0].instructions;
+        let select = instructions
+            .iter()
+            .position(|instruction| instruction == &Instruction::Select)
+            .unwrap();
+        assert!(matches!(
+            &instructions[select - 5..select],
+            [
+                Instruction::RangeCheck { bits: 2 },
+                Instruction::Dup,
+                Instruction::Push {
+                    value: Constant::Num { value: len }
+                },
+                Instruction::BinOp {
+                    op: BinaryOperator::LessThan
+                },
+                Instruction::Assert { message: Some(message), .. },
+            ] if *len == BigInt::from(3) && message == "index out of bounds"
+        ));
+
+        let run = |i: i64| {
+            let mut vm = VM::new(program.clone()).with_inputs(vec![], vec![i.into()]);
+            vm.execute().map(|_| vm.journal)
+        };
+        assert_eq!(run(2), Ok(vec![BigInt::from(6)]));
+        assert!(matches!(
+            run(3),
+            Err(VMError::AssertionFailed { message: Some(message), .. })
+                if message == "index out of bounds"
+        ));
+        assert!(matches!(
+            run(4),
+            Err(VMError::RangeCheckFailed { bits: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_tuples_and_structs() {
+        let program = compile(&parse_source(
+            "struct Point { x: field, y: field }
+            function double(p: Point) {
+                return (p.x + p.x, p.y + p.y);
+            }
+            let (a, b) = double(Point { y: 3, x: 2 });
+            let q = Point { x: a, y: b };
+            return q.x * 10 + q.y;",
+        ))
+        .unwrap();
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![Value::Num { value: 46.into() }]);
+    }
+
+    #[test]
+    fn test_struct_errors() {
+        let struct_error = |input| compile(&parse_source(input)).unwrap_err();
+
+        assert!(matches!(
+            struct_error("struct P { x: field } let p = P { x: 1, z: 2 };"),
+            CompileError::UnknownField { .. }
+        ));
+        assert!(matches!(
+            struct_error("struct P { x: field, y: field } let p = P { x: 1 };"),
+            CompileError::MissingField { .. }
+        ));
+        assert!(matches!(
+            struct_error("struct P { x: field } let p = P { x: 1, x: 2 };"),
+            CompileError::DuplicateField(..)
+        ));
+        assert!(matches!(
+            struct_error("let p = Q { x: 1 };"),
+            CompileError::UndefinedStruct(..)
+        ));
+        assert!(matches!(
+            struct_error("struct P { x: field } struct P { y: field }"),
+            CompileError::DuplicateStruct(..)
+        ));
+    }
+
+    #[test]
+    fn test_integer_range_checks() {
+        let run = |input| {
+            let mut vm = VM::new(compile_source(input));
+            vm.execute().map(|_| vm.data)
+        };
+
+        let input = "let a: u8 = 20; let b: u8 = 10; return a * b + 55;";
+        assert_eq!(run(input), Ok(vec![Value::Num { value: 255.into() }]));
+        assert_eq!(
+            run("let a: u8 = 200; let b: u8 = 100; return a + b;"),
+            Err(VMError::RangeCheckFailed {
+                value: 300.into(),
+                bits: 8
+            })
+        );
+        assert_eq!(
+            run("let a: u8 = 200; return !a;"),
+            Ok(vec![Value::Num { value: 55.into() }])
+        );
+        assert_eq!(
+            run("let a: u8 = 200; let b: u8 = 7; return (a - b) / b;"),
+            Ok(vec![Value::Num { value: 27.into() }])
+        );
+        assert_eq!(
+            run("let a: u8 = 7; let b: u8 = 200; return a - b;"),
+            Err(VMError::RangeCheckFailed {
+                value: Bn254.reduce(&(-193).into()),
+                bits: 8
+            })
+        );
+
+        // Typed parameters are constrained on entry; fields are not.
+        let program = compile_source("function f(a: u32, b: bool, c) { return a; }");
+        let checks: Vec<_> = program.functions["f"].blocks[0]
+            .instructions
+            .iter()
+            .filter(|i| matches!(i, Instruction::RangeCheck { .. }))
+            .collect();
+        assert_eq!(
+            checks,
+            vec![
+                &Instruction::RangeCheck { bits: 1 },
+                &Instruction::RangeCheck { bits: 32 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inputs() {
+        let input = "
+        struct Point { x: field, y: field }
+        public scale: field;
+        private p: Point;
+        private bits: [bool; 2];
+        return (p.x * scale + p.y, bits[1]);";
+        let program = compile_source(input);
+        assert_eq!(
+            program.inputs,
+            vec![
+                Input {
+                    name: String::from("scale"),
+                    visibility: Visibility::Public,
+                },
+                Input {
+                    name: String::from("p"),
+                    visibility: Visibility::Private,
+                },
+                Input {
+                    name: String::from("bits"),
+                    visibility: Visibility::Private,
+                },
+            ]
+        );
+
+        let run = |private: Vec<i64>| {
+            let private = private.into_iter().map(Into::into).collect();
+            let mut vm = VM::new(program.clone()).with_inputs(vec![10.into()], private);
+            vm.execute().map(|_| vm.data)
+        };
+        let num = |value: i64| Value::Num {
+            value: value.into(),
+        };
+        assert_eq!(
+            run(vec![3, 4, 0, 1]),
+            Ok(vec![Value::Tuple {
+                elements: vec![num(34), num(1)]
+            }])
+        );
+        // Private inputs are constrained to their declared types.
+        assert_eq!(
+            run(vec![3, 4, 0, 2]),
+            Err(VMError::RangeCheckFailed {
+                value: 2.into(),
+                bits: 1
+            })
+        );
+        assert_eq!(
+            run(vec![3, 4, 0]),
+            Err(VMError::MissingInput(Visibility::Private))
+        );
+
+        let result = compile(&parse_source("if true { public x: field; }"));
+        assert!(matches!(result, Err(CompileError::NestedInput(..))));
+    }
+
+    #[test]
+    fn test_journal() {
+        let input = "
+        struct Point { x: field, y: field }
+        function double(x) { commit(x); return x * 2; }
+        commit(Point { x: 1, y: 2 });
+        commit([true, false]);
+        return (double(5), 7);";
+        let program = compile_source(input);
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+
+        // Commits inside functions are public too, but a function's return
+        // value is not.
+        let expected: Vec<BigInt> = [1, 2, 1, 0, 5, 10, 7].into_iter().map(Into::into).collect();
+        assert_eq!(vm.journal, expected);
+        assert_eq!(vm.data.len(), 1);
+    }
+
+    #[test]
+    fn test_assertions() {
+        let input = "
+        private x: field;
+        assert x != 0;
+        assert(x == 3, \"x must be 3\");
+        return x;";
+        let program = compile_source(input);
+        let run = |x: i64| {
+            VM::new(program.clone())
+                .with_inputs(vec![], vec![x.into()])
+                .execute()
+                .err()
+        };
+
+        assert_eq!(run(3), None);
+        let Some(VMError::AssertionFailed {
+            message,
+            block,
+            ip,
+            span,
+        }) = run(0)
+        else {
+            panic!("expected an assertion failure");
+        };
+        assert_eq!(message, None);
+        assert_eq!(&input[span.start..span.end], "assert x != 0;");
+        assert!(matches!(
+            program.blocks[block].instructions[ip],
+            Instruction::Assert { .. }
+        ));
+
+        let error = run(4).unwrap();
+        assert_eq!(error.to_string(), "4:9: assertion failed: x must be 3");
+    }
+
+    #[test]
+    fn test_constants() {
+        let input = "
+        function sum(xs: [field; LEAVES]) {
+            let mut total = 0;
+            for i in 0..LEAVES { total += xs[i]; }
+            return total + DEPTH;
+        }
+        const DEPTH = 3;
+        const LEAVES = 1 << DEPTH;
+        struct Path { bits: [bool; DEPTH] }
+        private path: Path;
+        let xs: [field; LEAVES] = [1, 2, 3, 4, 5, 6, 7, 8];
+        return (sum(xs), path.bits[DEPTH - 1]);";
+        let program = compile_source(input);
+        // `DEPTH - 1` is folded, so the element is addressed directly.
+        assert!(program.blocks[0]
+            .instructions
+            .contains(&Instruction::Get { index: 2 }));
+
+        let private = vec![0.into(), 0.into(), 1.into()];
+        let mut vm = VM::new(program).with_inputs(vec![], private);
+        vm.execute().unwrap();
+        let num = |value: i64| Value::Num {
+            value: value.into(),
+        };
+        assert_eq!(
+            vm.data,
+            vec![Value::Tuple {
+                elements: vec![num(39), num(1)]
+            }]
+        );
+
+        let error = |input| compile(&parse_source(input)).unwrap_err();
+        assert!(matches!(
+            error("let n = 2; let a: [field; n] = [1, 2];"),
+            CompileError::NonConstantLength(_)
+        ));
+        assert!(matches!(
+            error("let n = 2; for i in 0..n { }"),
+            CompileError::NonConstantLoopBound(_)
+        ));
+        assert!(matches!(
+            error("const BIG = 300; let x: u8 = BIG;"),
+            CompileError::LiteralOutOfRange { .. }
+        ));
+        assert!(matches!(
+            error("if true { const N = 1; }"),
+            CompileError::NestedConst(..)
+        ));
+    }
+}
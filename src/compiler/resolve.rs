@@ -0,0 +1,468 @@
+use std::{collections::HashMap, fmt};
+
+use super::CompileError;
+use crate::{
+    ast::ast::{self, BindingId, Expression, Identifier, Statement},
+    token::token::Span,
+};
+
+/// A variable declaration: a `let`, input, function parameter or loop variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub span: Span,
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A declaration hides one from an enclosing scope.
+    Shadowing {
+        name: String,
+        span: Span,
+        shadowed: Span,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::Shadowing {
+                name,
+                span,
+                shadowed,
+            } => write!(
+                f,
+                "{}: `{}` shadows the binding at {}",
+                span, name, shadowed
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// Every variable in the program, indexed by `BindingId`
+    pub symbols: Vec<Symbol>,
+    pub warnings: Vec<Warning>,
+}
+
+/// Binds every variable use in `program` to its declaration by filling in
+/// `Identifier::binding`. Each block, function body and loop body is a scope;
+/// functions don't see the variables of the code around them, only the
+/// top-level constants.
+///
+/// Functions and structs of imported modules are renamed to their qualified
+/// names, such as `lib::hash`, and hoisted to the top of `program` in place of
+/// the modules, so later passes see a single flat program.
+pub fn resolve(program: &mut ast::Program) -> Result<Resolution, CompileError> {
+    let mut resolver = Resolver {
+        scopes: vec![HashMap::new()],
+        constants: HashMap::new(),
+        qualified: HashMap::new(),
+        prefix: String::new(),
+        resolution: Resolution::default(),
+    };
+    resolver.module(program)?;
+    hoist(program);
+
+    Ok(resolver.resolution)
+}
+
+/// Replaces every module with its items, ahead of the code that imports it.
+fn hoist(program: &mut ast::Program) {
+    let mut items = vec![];
+    let mut rest = vec![];
+    for statement in program.drain(..) {
+        match statement {
+            Statement::Module(mut module) => {
+                hoist(&mut module.program);
+                items.append(&mut module.program);
+            }
+            statement => rest.push(statement),
+        }
+    }
+    items.append(&mut rest);
+    *program = items;
+}
+
+struct Resolver {
+    /// Names in scope, innermost scope last
+    scopes: Vec<HashMap<String, BindingId>>,
+    /// The current module's constants, the outermost scope of every function
+    constants: HashMap<String, BindingId>,
+    /// Every module's constants, by qualified name
+    qualified: HashMap<String, BindingId>,
+    /// Qualified name of the current module followed by `::`, or empty at the
+    /// top level
+    prefix: String,
+    resolution: Resolution,
+}
+
+impl Resolver {
+    fn module(&mut self, program: &mut ast::Program) -> Result<(), CompileError> {
+        // Imports first, so that constants here can use theirs.
+        for statement in program.iter_mut() {
+            match statement {
+                Statement::Module(module) => {
+                    let prefix = format!("{}{}::", self.prefix, module.name);
+                    let prefix = std::mem::replace(&mut self.prefix, prefix);
+                    let scopes = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+                    let constants = std::mem::take(&mut self.constants);
+                    let result = self.module(&mut module.program);
+                    self.prefix = prefix;
+                    self.scopes = scopes;
+                    self.constants = constants;
+                    result?;
+                }
+                Statement::Import { path, span } => {
+                    return Err(CompileError::UnloadedImport(path.clone(), *span));
+                }
+                _ => {}
+            }
+        }
+
+        // Constants are visible from the whole module, but each one only sees
+        // those declared before it.
+        for statement in program.iter_mut() {
+            if let Statement::Const { name, value, .. } = statement {
+                self.expression(value)?;
+                self.declare(name, false)?;
+                if let Some(id) = name.binding {
+                    self.qualified
+                        .insert(format!("{}{}", self.prefix, name.name), id);
+                }
+            }
+        }
+        self.constants = self.scopes[0].clone();
+
+        for statement in program.iter_mut() {
+            match statement {
+                Statement::Function(function) => self.function(function)?,
+                Statement::Const { .. } | Statement::Module(_) => {}
+                statement => self.statement(statement)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames a function or struct of the current module to its qualified name.
+    fn qualify(&self, name: &mut Identifier) {
+        if !self.prefix.is_empty() {
+            name.name = format!("{}{}", self.prefix, name.name);
+        }
+    }
+
+    fn declare(&mut self, identifier: &mut Identifier, mutable: bool) -> Result<(), CompileError> {
+        let scope = self.scopes.last().expect("resolver always has a scope");
+        if let Some(&id) = scope.get(&identifier.name) {
+            return Err(CompileError::DuplicateDefinition {
+                name: identifier.name.clone(),
+                span: identifier.span,
+                previous: self.resolution.symbols[id].span,
+            });
+        }
+        if let Some(&id) = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&identifier.name))
+        {
+            self.resolution.warnings.push(Warning::Shadowing {
+                name: identifier.name.clone(),
+                span: identifier.span,
+                shadowed: self.resolution.symbols[id].span,
+            });
+        }
+
+        let id = self.resolution.symbols.len();
+        self.resolution.symbols.push(Symbol {
+            name: identifier.name.clone(),
+            span: identifier.span,
+            mutable,
+        });
+        self.scopes
+            .last_mut()
+            .expect("resolver always has a scope")
+            .insert(identifier.name.clone(), id);
+        identifier.binding = Some(id);
+        Ok(())
+    }
+
+    fn lookup(&self, identifier: &mut Identifier) -> Result<(), CompileError> {
+        let id = if identifier.name.contains("::") {
+            self.qualified
+                .get(&format!("{}{}", self.prefix, identifier.name))
+        } else {
+            self.scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&identifier.name))
+        }
+        .ok_or_else(|| CompileError::UndefinedVariable(identifier.name.clone(), identifier.span))?;
+        identifier.binding = Some(*id);
+        Ok(())
+    }
+
+    fn pattern(&mut self, pattern: &mut ast::Pattern, mutable: bool) -> Result<(), CompileError> {
+        match pattern {
+            ast::Pattern::Identifier(name) => self.declare(name, mutable),
+            ast::Pattern::Tuple(patterns, _) => patterns
+                .iter_mut()
+                .try_for_each(|pattern| self.pattern(pattern, mutable)),
+        }
+    }
+
+    /// Runs `resolve` in a new scope.
+    fn scoped<T>(&mut self, resolve: impl FnOnce(&mut Self) -> T) -> T {
+        self.scopes.push(HashMap::new());
+        let result = resolve(self);
+        self.scopes.pop();
+        result
+    }
+
+    fn function(&mut self, function: &mut ast::Function) -> Result<(), CompileError> {
+        self.qualify(&mut function.name);
+        let scopes = std::mem::replace(
+            &mut self.scopes,
+            vec![self.constants.clone(), HashMap::new()],
+        );
+        let result = function
+            .params
+            .iter_mut()
+            .try_for_each(|param| {
+                if let Some(ty) = &mut param.ty {
+                    self.ty(ty)?;
+                }
+                self.declare(&mut param.name, false)
+            })
+            .and_then(|_| self.block(&mut function.body));
+        self.scopes = scopes;
+        result
+    }
+
+    fn block(&mut self, block: &mut ast::Block) -> Result<(), CompileError> {
+        self.scoped(|resolver| {
+            block
+                .0
+                .iter_mut()
+                .try_for_each(|statement| resolver.statement(statement))
+        })
+    }
+
+    fn statement(&mut self, statement: &mut Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Let {
+                pattern,
+                mutable,
+                ty,
+                value,
+                ..
+            } => {
+                if let Some(ty) = ty {
+                    self.ty(ty)?;
+                }
+                // The value can still see what the pattern is about to shadow.
+                self.expression(value)?;
+                self.pattern(pattern, *mutable)?;
+            }
+            Statement::Assign { target, value, .. } => {
+                self.lookup(target)?;
+                self.expression(value)?;
+            }
+            Statement::Return(value, _)
+            | Statement::Assert(value, _, _)
+            | Statement::Commit(value, _)
+            | Statement::Expression(value, _) => self.expression(value)?,
+            Statement::Ifelse(condition, then_block, else_block, _) => {
+                self.expression(condition)?;
+                self.block(then_block)?;
+                self.block(else_block)?;
+            }
+            Statement::For {
+                var,
+                start,
+                end,
+                body,
+                ..
+            } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                self.scoped(|resolver| {
+                    resolver.declare(var, false)?;
+                    resolver.block(body)
+                })?;
+            }
+            Statement::Input { name, ty, .. } => {
+                self.ty(ty)?;
+                self.declare(name, false)?;
+            }
+            Statement::Struct(declaration) => {
+                self.qualify(&mut declaration.name);
+                for field in &mut declaration.fields {
+                    self.ty(&mut field.ty)?;
+                }
+            }
+            // Lowering rejects declarations that aren't at the top level.
+            Statement::Function(_)
+            | Statement::Const { .. }
+            | Statement::Import { .. }
+            | Statement::Module(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Resolves the array lengths in `ty`, which may name constants.
+    fn ty(&mut self, ty: &mut ast::Type) -> Result<(), CompileError> {
+        match ty {
+            ast::Type::Array(element, len) => {
+                self.ty(element)?;
+                self.expression(len)
+            }
+            ast::Type::Tuple(elements) => elements.iter_mut().try_for_each(|ty| self.ty(ty)),
+            ast::Type::Named(name) => {
+                self.qualify(name);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn expression(&mut self, expression: &mut Expression) -> Result<(), CompileError> {
+        match expression {
+            Expression::Identifier(identifier) => self.lookup(identifier)?,
+            Expression::Literal(..) => {}
+            Expression::BinaryOperation(_, lhs, rhs, _) => {
+                self.expression(lhs)?;
+                self.expression(rhs)?;
+            }
+            Expression::UnaryOperation(_, operand, _) => self.expression(operand)?,
+            Expression::Call(name, args, _) => {
+                self.qualify(name);
+                for arg in args {
+                    self.expression(arg)?;
+                }
+            }
+            Expression::Array(args, _) | Expression::Tuple(args, _) => {
+                for arg in args {
+                    self.expression(arg)?;
+                }
+            }
+            Expression::If(condition, then_block, else_block, _) => {
+                self.expression(condition)?;
+                self.block(then_block)?;
+                self.block(else_block)?;
+            }
+            Expression::Index(array, index, _) => {
+                self.expression(array)?;
+                self.expression(index)?;
+            }
+            Expression::Struct(name, fields, _) => {
+                self.qualify(name);
+                for (_, value) in fields {
+                    self.expression(value)?;
+                }
+            }
+            Expression::Field(value, _, _) => self.expression(value)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, Warning};
+    use crate::ast::ast::{Expression, Pattern, Program, Statement};
+    use crate::compiler::CompileError;
+    use crate::lexer::lexer::Lexer;
+    use crate::parser::parser::Parser;
+
+    fn parse(input: &str) -> Program {
+        Parser::new(Lexer::new(input)).parse_program().unwrap()
+    }
+
+    #[test]
+    fn test_binding_ids() {
+        let mut program = parse("let x = 1; if true { let x = x; x; } x;");
+        let resolution = resolve(&mut program).unwrap();
+
+        let names: Vec<&str> = resolution.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["x", "x"]);
+
+        let (outer, inner) = match &program[1] {
+            Statement::Ifelse(_, block, _, _) => match (&block.0[0], &block.0[1]) {
+                (
+                    Statement::Let {
+                        pattern: Pattern::Identifier(declared),
+                        value: Expression::Identifier(initializer),
+                        ..
+                    },
+                    Statement::Expression(Expression::Identifier(used), _),
+                ) => {
+                    assert_eq!(declared.binding, used.binding);
+                    (initializer.binding, used.binding)
+                }
+                statements => panic!("unexpected statements {:?}", statements),
+            },
+            statement => panic!("expected if statement, got {:?}", statement),
+        };
+        assert_eq!((outer, inner), (Some(0), Some(1)));
+        match &program[2] {
+            Statement::Expression(Expression::Identifier(x), _) => assert_eq!(x.binding, Some(0)),
+            statement => panic!("expected expression, got {:?}", statement),
+        }
+
+        assert_eq!(resolution.warnings.len(), 1);
+        assert_eq!(
+            resolution.warnings[0].to_string(),
+            "1:26: `x` shadows the binding at 1:5"
+        );
+        assert!(matches!(
+            &resolution.warnings[0],
+            Warning::Shadowing { name, .. } if name == "x"
+        ));
+    }
+
+    #[test]
+    fn test_resolution_errors() {
+        let error = |input| resolve(&mut parse(input)).unwrap_err();
+
+        assert!(matches!(
+            error("let x = 1; let x = 2;"),
+            CompileError::DuplicateDefinition { .. }
+        ));
+        assert!(matches!(
+            error("let (a, a) = (1, 2);"),
+            CompileError::DuplicateDefinition { .. }
+        ));
+        assert!(matches!(
+            error("function f(a, a) { }"),
+            CompileError::DuplicateDefinition { .. }
+        ));
+        assert!(matches!(
+            error("let y = y;"),
+            CompileError::UndefinedVariable(..)
+        ));
+        // Functions don't capture the variables around them.
+        assert!(matches!(
+            error("let x = 1; function f() { return x; }"),
+            CompileError::UndefinedVariable(..)
+        ));
+        assert!(matches!(
+            error("for i in 0..2 { } i;"),
+            CompileError::UndefinedVariable(..)
+        ));
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let input = "if (true) { let y = 1; }\nreturn y;";
+        match resolve(&mut parse(input)).unwrap_err() {
+            CompileError::UndefinedVariable(name, span) => {
+                assert_eq!(name, "y");
+                assert_eq!((span.line, span.column), (2, 8));
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+}
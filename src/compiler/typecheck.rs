@@ -0,0 +1,800 @@
+use std::{collections::HashMap, fmt};
+
+use num_bigint::{BigInt, Sign};
+use num_traits::ToPrimitive;
+
+use super::{
+    consteval::{self, Constants},
+    CompileError,
+};
+use crate::{
+    ast::ast::{self, BinaryOperator, BindingId, Expression, Literal, Statement, UnaryOperator},
+    token::token::Span,
+};
+
+/// A resolved type. Unlike `ast::Type`, array lengths are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ty {
+    Field,
+    Bool,
+    /// Unsigned integer of the given bit width
+    Uint(u32),
+    Array(Box<Ty>, usize),
+    /// `()` is the empty tuple, which is also the unit type.
+    Tuple(Vec<Ty>),
+    Struct(String),
+}
+
+impl Ty {
+    pub fn unit() -> Ty {
+        Ty::Tuple(vec![])
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Ty::Field | Ty::Uint(_))
+    }
+
+    fn is_scalar(&self) -> bool {
+        matches!(self, Ty::Field | Ty::Bool | Ty::Uint(_))
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::Field => write!(f, "field"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::Uint(bits) => write!(f, "u{}", bits),
+            Ty::Array(element, len) => write!(f, "[{}; {}]", element, len),
+            Ty::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(Ty::to_string).collect();
+                write!(f, "({})", elements.join(", "))
+            }
+            Ty::Struct(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Types of the expressions in a program, for lowering.
+#[derive(Debug, Default)]
+pub struct Types {
+    /// Keyed by source range, which is unique per expression within a file.
+    expressions: HashMap<(usize, usize), Ty>,
+    /// Parameter types of every function
+    params: HashMap<String, Vec<Ty>>,
+    variables: HashMap<BindingId, Ty>,
+    /// Field names and types of every struct, in declaration order
+    structs: HashMap<String, Vec<(String, Ty)>>,
+    constants: Constants,
+}
+
+impl Types {
+    /// Value of `identifier` if it names a `const`.
+    pub fn constant(&self, identifier: &ast::Identifier) -> Option<&BigInt> {
+        identifier.binding.and_then(|id| self.constants.get(&id))
+    }
+
+    pub fn constants(&self) -> &Constants {
+        &self.constants
+    }
+
+    /// Value of `expression` if it is a compile-time constant.
+    pub fn evaluate(&self, expression: &Expression) -> Option<BigInt> {
+        consteval::evaluate(expression, &self.constants).ok()
+    }
+
+    pub fn get(&self, expression: &Expression) -> Option<&Ty> {
+        self.expressions.get(&key(expression.span()))
+    }
+
+    pub fn params(&self, function: &str) -> &[Ty] {
+        self.params.get(function).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn variable(&self, identifier: &ast::Identifier) -> Option<&Ty> {
+        identifier.binding.and_then(|id| self.variables.get(&id))
+    }
+
+    pub fn fields(&self, name: &str) -> &[(String, Ty)] {
+        self.structs.get(name).map_or(&[], Vec::as_slice)
+    }
+}
+
+fn key(span: Span) -> (usize, usize) {
+    (span.start, span.end)
+}
+
+/// Infers and checks the type of every expression in `program`. Unannotated
+/// function parameters are `field`, and a function's return type is inferred
+/// from its `return` statements. Constants are evaluated first, and are typed
+/// like the integer literals they stand for.
+pub fn check(program: &ast::Program) -> Result<Types, CompileError> {
+    let mut checker = Checker::default();
+    checker.types.constants = consteval::evaluate_constants(program)?;
+
+    for statement in program {
+        match statement {
+            Statement::Function(function) => {
+                checker
+                    .functions
+                    .insert(function.name.name.clone(), function);
+            }
+            Statement::Struct(declaration) => {
+                checker
+                    .structs
+                    .insert(declaration.name.name.clone(), vec![]);
+            }
+            _ => {}
+        }
+    }
+    for statement in program {
+        if let Statement::Struct(declaration) = statement {
+            let fields = declaration
+                .fields
+                .iter()
+                .map(|field| Ok((field.name.name.clone(), checker.resolve(&field.ty)?)))
+                .collect::<Result<_, CompileError>>()?;
+            checker
+                .structs
+                .insert(declaration.name.name.clone(), fields);
+        }
+    }
+
+    for statement in program {
+        if let Statement::Function(function) = statement {
+            checker.return_type(&function.name)?;
+        }
+    }
+    for statement in program {
+        checker.statement(statement)?;
+    }
+
+    checker.types.structs = checker.structs;
+    Ok(checker.types)
+}
+
+#[derive(Default)]
+struct Checker<'a> {
+    functions: HashMap<String, &'a ast::Function>,
+    /// Field names and types of every struct, in declaration order
+    structs: HashMap<String, Vec<(String, Ty)>>,
+    /// Return types of the functions checked so far, `None` while one is
+    /// still being checked
+    returns: HashMap<String, Option<Ty>>,
+    /// Return type of the code being checked, once a `return` has fixed it
+    return_ty: Option<Ty>,
+    types: Types,
+}
+
+impl<'a> Checker<'a> {
+    fn resolve(&self, ty: &ast::Type) -> Result<Ty, CompileError> {
+        Ok(match ty {
+            ast::Type::Field => Ty::Field,
+            ast::Type::Bool => Ty::Bool,
+            ast::Type::U8 => Ty::Uint(8),
+            ast::Type::U32 => Ty::Uint(32),
+            ast::Type::U64 => Ty::Uint(64),
+            ast::Type::Array(element, len) => {
+                let len = match consteval::evaluate(len, &self.types.constants) {
+                    Ok(value) => value.to_usize(),
+                    Err(CompileError::NotConstant(_)) => None,
+                    Err(error) => return Err(error),
+                }
+                .ok_or(CompileError::NonConstantLength(len.span()))?;
+                Ty::Array(Box::new(self.resolve(element)?), len)
+            }
+            ast::Type::Tuple(elements) => Ty::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.resolve(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ast::Type::Named(name) => {
+                if !self.structs.contains_key(&name.name) {
+                    return Err(CompileError::UndefinedStruct(name.name.clone(), name.span));
+                }
+                Ty::Struct(name.name.clone())
+            }
+        })
+    }
+
+    fn lookup(&self, identifier: &ast::Identifier) -> Result<Ty, CompileError> {
+        self.types.variable(identifier).cloned().ok_or_else(|| {
+            CompileError::UndefinedVariable(identifier.name.clone(), identifier.span)
+        })
+    }
+
+    fn declare(&mut self, identifier: &ast::Identifier, ty: Ty) {
+        if let Some(id) = identifier.binding {
+            self.types.variables.insert(id, ty);
+        }
+    }
+
+    fn bind(&mut self, pattern: &ast::Pattern, ty: Ty) -> Result<(), CompileError> {
+        match pattern {
+            ast::Pattern::Identifier(name) => self.declare(name, ty),
+            ast::Pattern::Tuple(patterns, span) => match ty {
+                Ty::Tuple(elements) if elements.len() == patterns.len() => {
+                    for (pattern, ty) in patterns.iter().zip(elements) {
+                        self.bind(pattern, ty)?;
+                    }
+                }
+                found => {
+                    return Err(CompileError::PatternMismatch {
+                        len: patterns.len(),
+                        found,
+                        span: *span,
+                    })
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn return_type(&mut self, name: &ast::Identifier) -> Result<Ty, CompileError> {
+        match self.returns.get(&name.name) {
+            Some(Some(ty)) => return Ok(ty.clone()),
+            Some(None) => {
+                return Err(CompileError::RecursiveFunction(
+                    name.name.clone(),
+                    name.span,
+                ))
+            }
+            None => {}
+        }
+        let function = *self
+            .functions
+            .get(&name.name)
+            .ok_or_else(|| CompileError::UndefinedFunction(name.name.clone(), name.span))?;
+        self.returns.insert(name.name.clone(), None);
+
+        let params = self.params(function)?;
+        self.types.params.insert(name.name.clone(), params.clone());
+        let return_ty = self.return_ty.take();
+        for (param, ty) in function.params.iter().zip(params) {
+            self.declare(&param.name, ty);
+        }
+        let result = self.statements(&function.body.0);
+        let ty = std::mem::replace(&mut self.return_ty, return_ty).unwrap_or_else(Ty::unit);
+        result?;
+        // Falling off the end returns unit, which only a unit function may do.
+        if ty != Ty::unit() && !always_returns(&function.body.0) {
+            return Err(CompileError::MissingReturn(
+                name.name.clone(),
+                function.name.span,
+            ));
+        }
+
+        self.returns.insert(name.name.clone(), Some(ty.clone()));
+        Ok(ty)
+    }
+
+    fn params(&self, function: &ast::Function) -> Result<Vec<Ty>, CompileError> {
+        function
+            .params
+            .iter()
+            .map(|param| match &param.ty {
+                Some(ty) => self.resolve(ty),
+                None => Ok(Ty::Field),
+            })
+            .collect()
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &ast::Block) -> Result<(), CompileError> {
+        self.statements(&block.0)
+    }
+
+    /// Type of a block ending in an expression; `span` is reported if it doesn't.
+    fn block_value(
+        &mut self,
+        block: &ast::Block,
+        expected: Option<&Ty>,
+        span: Span,
+    ) -> Result<Ty, CompileError> {
+        let (value, statements) = match block.0.split_last() {
+            Some((Statement::Expression(value, _), statements)) => (value, statements),
+            _ => return Err(CompileError::MissingBranchValue(span)),
+        };
+
+        self.statements(statements)?;
+        self.expression(value, expected)
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Let {
+                pattern, ty, value, ..
+            } => {
+                let ty = match ty {
+                    Some(ty) => {
+                        let ty = self.resolve(ty)?;
+                        self.expect(&ty, value)?;
+                        ty
+                    }
+                    None => self.expression(value, None)?,
+                };
+                self.bind(pattern, ty)?;
+            }
+            Statement::Assign {
+                target, op, value, ..
+            } => {
+                let ty = self.lookup(target)?;
+                self.expect(&ty, value)?;
+                if let Some(op) = op {
+                    self.check_operator(op, &ty, statement.span())?;
+                }
+            }
+            Statement::Return(value, _) => match self.return_ty.clone() {
+                Some(ty) => self.expect(&ty, value)?,
+                None => self.return_ty = Some(self.expression(value, None)?),
+            },
+            Statement::Ifelse(condition, then_block, else_block, _) => {
+                self.expect(&Ty::Bool, condition)?;
+                self.block(then_block)?;
+                self.block(else_block)?;
+            }
+            Statement::Assert(condition, _, _) => self.expect(&Ty::Bool, condition)?,
+            Statement::Expression(value, _) | Statement::Commit(value, _) => {
+                self.expression(value, None)?;
+            }
+            Statement::For {
+                var,
+                start,
+                end,
+                body,
+                ..
+            } => {
+                // The bounds can be of any integer type, which the variable takes.
+                let ty = self.operands(start, end, None)?;
+                if !ty.is_integer() {
+                    return Err(CompileError::TypeMismatch {
+                        expected: Ty::Field,
+                        found: ty,
+                        span: start.span(),
+                    });
+                }
+                self.declare(var, ty);
+                self.block(body)?;
+            }
+            Statement::Input { name, ty, .. } => {
+                let ty = self.resolve(ty)?;
+                self.declare(name, ty);
+            }
+            // Lowering rejects declarations that aren't at the top level.
+            Statement::Function(_)
+            | Statement::Struct(_)
+            | Statement::Const { .. }
+            | Statement::Import { .. }
+            | Statement::Module(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Checks that `expression` has type `ty`.
+    fn expect(&mut self, ty: &Ty, expression: &Expression) -> Result<(), CompileError> {
+        let found = self.expression(expression, Some(ty))?;
+        if &found != ty {
+            return Err(CompileError::TypeMismatch {
+                expected: ty.clone(),
+                found,
+                span: expression.span(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Infers the type of `expression`. `expected` only guides integer literals;
+    /// the caller still has to compare the result.
+    fn expression(
+        &mut self,
+        expression: &Expression,
+        expected: Option<&Ty>,
+    ) -> Result<Ty, CompileError> {
+        let ty = self.expression_inner(expression, expected)?;
+        self.types
+            .expressions
+            .insert(key(expression.span()), ty.clone());
+        Ok(ty)
+    }
+
+    fn expression_inner(
+        &mut self,
+        expression: &Expression,
+        expected: Option<&Ty>,
+    ) -> Result<Ty, CompileError> {
+        Ok(match expression {
+            Expression::Identifier(identifier) => match self.types.constant(identifier) {
+                Some(value) => integer(value, expected, identifier.span)?,
+                None => self.lookup(identifier)?,
+            },
+            Expression::Literal(Literal::Int(value), span) => integer(value, expected, *span)?,
+            Expression::Literal(Literal::Bool(_), _) => Ty::Bool,
+            Expression::BinaryOperation(op, lhs, rhs, span) => {
+                self.binary(op, lhs, rhs, expected, *span)?
+            }
+            Expression::UnaryOperation(op, operand, span) => {
+                let ty = self.expression(operand, expected)?;
+                let valid = match op {
+                    UnaryOperator::Minus => ty == Ty::Field,
+                    UnaryOperator::Not => matches!(ty, Ty::Bool | Ty::Uint(_)),
+                };
+                if !valid {
+                    return Err(CompileError::InvalidOperand {
+                        op: op.to_string(),
+                        ty,
+                        span: *span,
+                    });
+                }
+                ty
+            }
+            Expression::Call(name, args, span) => {
+                let function = *self
+                    .functions
+                    .get(&name.name)
+                    .ok_or_else(|| CompileError::UndefinedFunction(name.name.clone(), name.span))?;
+                if function.params.len() != args.len() {
+                    return Err(CompileError::ArityMismatch {
+                        name: name.name.clone(),
+                        expected: function.params.len(),
+                        found: args.len(),
+                        span: *span,
+                    });
+                }
+                for (arg, ty) in args.iter().zip(self.params(function)?) {
+                    self.expect(&ty, arg)?;
+                }
+                self.return_type(name)?
+            }
+            Expression::If(condition, then_block, else_block, span) => {
+                self.expect(&Ty::Bool, condition)?;
+                let ty = self.block_value(then_block, expected, *span)?;
+                let found = self.block_value(else_block, Some(&ty), *span)?;
+                if found != ty {
+                    return Err(CompileError::TypeMismatch {
+                        expected: ty,
+                        found,
+                        span: *span,
+                    });
+                }
+                ty
+            }
+            Expression::Array(elements, span) => {
+                let element = match expected {
+                    Some(Ty::Array(element, _)) => Some(element.as_ref()),
+                    _ => None,
+                };
+                let element = match elements.split_first() {
+                    Some((first, rest)) => {
+                        let ty = self.expression(first, element)?;
+                        for other in rest {
+                            self.expect(&ty, other)?;
+                        }
+                        ty
+                    }
+                    None => element.cloned().ok_or(CompileError::EmptyArray(*span))?,
+                };
+                Ty::Array(Box::new(element), elements.len())
+            }
+            Expression::Index(array, index, span) => {
+                let (element, len) = match self.expression(array, None)? {
+                    Ty::Array(element, len) => (*element, len),
+                    found => {
+                        return Err(CompileError::NotAnArray {
+                            found,
+                            span: array.span(),
+                        })
+                    }
+                };
+                let ty = self.expression(index, None)?;
+                if !ty.is_integer() {
+                    return Err(CompileError::InvalidIndex { ty, span: *span });
+                }
+                if let Some(value) = self.types.evaluate(index) {
+                    if value >= BigInt::from(len) {
+                        return Err(CompileError::IndexOutOfBounds {
+                            index: value,
+                            len,
+                            span: *span,
+                        });
+                    }
+                }
+                element
+            }
+            Expression::Tuple(elements, _) => {
+                let expected = match expected {
+                    Some(Ty::Tuple(types)) if types.len() == elements.len() => Some(types),
+                    _ => None,
+                };
+                let types = elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| self.expression(element, expected.map(|types| &types[i])))
+                    .collect::<Result<_, _>>()?;
+                Ty::Tuple(types)
+            }
+            Expression::Struct(name, values, _) => {
+                self.struct_literal(name, values)?;
+                Ty::Struct(name.name.clone())
+            }
+            Expression::Field(value, field, span) => match self.expression(value, None)? {
+                Ty::Struct(name) => self.structs[&name]
+                    .iter()
+                    .find(|(other, _)| other == &field.name)
+                    .map(|(_, ty)| ty.clone())
+                    .ok_or_else(|| CompileError::UnknownField {
+                        name,
+                        field: field.name.clone(),
+                        span: field.span,
+                    })?,
+                found => return Err(CompileError::NotAStruct { found, span: *span }),
+            },
+        })
+    }
+
+    fn struct_literal(
+        &mut self,
+        name: &ast::Identifier,
+        values: &[(ast::Identifier, Expression)],
+    ) -> Result<(), CompileError> {
+        let fields = self
+            .structs
+            .get(&name.name)
+            .cloned()
+            .ok_or_else(|| CompileError::UndefinedStruct(name.name.clone(), name.span))?;
+
+        for (i, (field, value)) in values.iter().enumerate() {
+            if values[..i]
+                .iter()
+                .any(|(other, _)| other.name == field.name)
+            {
+                return Err(CompileError::DuplicateField(field.name.clone(), field.span));
+            }
+            let ty = fields
+                .iter()
+                .find(|(other, _)| other == &field.name)
+                .map(|(_, ty)| ty)
+                .ok_or_else(|| CompileError::UnknownField {
+                    name: name.name.clone(),
+                    field: field.name.clone(),
+                    span: field.span,
+                })?;
+            self.expect(ty, value)?;
+        }
+        if let Some((field, _)) = fields
+            .iter()
+            .find(|(field, _)| !values.iter().any(|(given, _)| &given.name == field))
+        {
+            return Err(CompileError::MissingField {
+                name: name.name.clone(),
+                field: field.clone(),
+                span: name.span,
+            });
+        }
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        op: &BinaryOperator,
+        lhs: &Expression,
+        rhs: &Expression,
+        expected: Option<&Ty>,
+        span: Span,
+    ) -> Result<Ty, CompileError> {
+        use BinaryOperator::*;
+
+        let ty = match op {
+            ShiftLeft | ShiftRight => {
+                let ty = self.expression(lhs, expected)?;
+                let amount = self.expression(rhs, None)?;
+                if !amount.is_integer() {
+                    return Err(CompileError::InvalidOperand {
+                        op: op.to_string(),
+                        ty: amount,
+                        span,
+                    });
+                }
+                ty
+            }
+            Equal | NotEqual | LessThan | GreaterThan | LessEqual | GreaterEqual => {
+                self.operands(lhs, rhs, None)?
+            }
+            And | Or => self.operands(lhs, rhs, Some(&Ty::Bool))?,
+            Plus | Minus | Multiply | Divide | Modulo | Xor | BitAnd | BitOr => {
+                self.operands(lhs, rhs, expected)?
+            }
+        };
+        self.check_operator(op, &ty, span)?;
+
+        Ok(match op {
+            Equal | NotEqual | LessThan | GreaterThan | LessEqual | GreaterEqual => Ty::Bool,
+            _ => ty,
+        })
+    }
+
+    /// Checks that both operands have the same type and returns it. An integer
+    /// literal or constant takes the type of the other side.
+    fn operands(
+        &mut self,
+        lhs: &Expression,
+        rhs: &Expression,
+        expected: Option<&Ty>,
+    ) -> Result<Ty, CompileError> {
+        let is_literal = |e: &Expression| match e {
+            Expression::Literal(Literal::Int(_), _) => true,
+            Expression::Identifier(identifier) => self.types.constant(identifier).is_some(),
+            _ => false,
+        };
+        let (first, second) = if is_literal(lhs) && !is_literal(rhs) {
+            (rhs, lhs)
+        } else {
+            (lhs, rhs)
+        };
+
+        let ty = self.expression(first, expected)?;
+        self.expect(&ty, second)?;
+        Ok(ty)
+    }
+
+    /// Checks that `op` applies to operands of type `ty`.
+    fn check_operator(&self, op: &BinaryOperator, ty: &Ty, span: Span) -> Result<(), CompileError> {
+        use BinaryOperator::*;
+
+        let valid = match op {
+            Plus | Minus | Multiply | Divide | Modulo | LessThan | GreaterThan | LessEqual
+            | GreaterEqual | ShiftLeft | ShiftRight => ty.is_integer(),
+            Equal | NotEqual => ty.is_scalar(),
+            And | Or => ty == &Ty::Bool,
+            Xor | BitAnd | BitOr => ty.is_scalar(),
+        };
+        if !valid {
+            return Err(CompileError::InvalidOperand {
+                op: op.to_string(),
+                ty: ty.clone(),
+                span,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Whether every path through `statements` ends in a `return`. Loops don't
+/// count, as their body may run zero times.
+fn always_returns(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Return(..) => true,
+        Statement::Ifelse(_, then_block, else_block, _) => {
+            always_returns(&then_block.0) && always_returns(&else_block.0)
+        }
+        _ => false,
+    })
+}
+
+/// Type of an integer literal: the expected unsigned type if there is one and
+/// the value fits, `field` otherwise.
+fn integer(value: &BigInt, expected: Option<&Ty>, span: Span) -> Result<Ty, CompileError> {
+    match expected {
+        Some(Ty::Uint(bits)) => {
+            if value.sign() == Sign::Minus || value.bits() > *bits as u64 {
+                return Err(CompileError::LiteralOutOfRange {
+                    value: value.clone(),
+                    ty: Ty::Uint(*bits),
+                    span,
+                });
+            }
+            Ok(Ty::Uint(*bits))
+        }
+        _ => Ok(Ty::Field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Ty};
+    use crate::compiler::{resolve::resolve, CompileError};
+    use crate::lexer::lexer::Lexer;
+    use crate::parser::parser::Parser;
+
+    fn check_source(input: &str) -> Result<(), CompileError> {
+        let mut program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        resolve(&mut program)?;
+        check(&program).map(|_| ())
+    }
+
+    #[test]
+    fn test_inference() {
+        let input = "
+        struct Pair { left: u8, right: [u32; 2] }
+        function pick(a: u8, b: bool) {
+            if b { return a + 1; }
+            return !a;
+        }
+        let x: u8 = pick(200, true);
+        let p = Pair { left: x, right: [1, 2] };
+        let y = p.right[1] * 3;
+        let z: u32 = y << 2;
+        assert(x < 255 && z != 0);";
+
+        assert_eq!(check_source(input), Ok(()));
+
+        // The loop variable takes the type of the bounds.
+        let input = "let n: u8 = 2; for i in 0..n { let x: u8 = i; }";
+        assert_eq!(check_source(input), Ok(()));
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let error = |input| check_source(input).unwrap_err();
+
+        let mismatch = error("let x: bool = 1;");
+        assert_eq!(mismatch.to_string(), "1:15: expected `bool`, found `field`");
+        assert!(matches!(
+            error("let a: u8 = 1; let b: u32 = 2; a + b;"),
+            CompileError::TypeMismatch {
+                expected: Ty::Uint(8),
+                found: Ty::Uint(32),
+                ..
+            }
+        ));
+        // A branch's trailing `if` gives the branch its value.
+        assert!(matches!(
+            error("let y: u8 = if true { if false { 1 } else { true } } else { 3 };"),
+            CompileError::TypeMismatch {
+                expected: Ty::Uint(8),
+                found: Ty::Bool,
+                ..
+            }
+        ));
+        // `f(false)` would fall through and return unit instead of a `field`.
+        let missing = error("function f(c: bool) {\n  if c { return 1; }\n}\nlet x = f(false);");
+        assert!(matches!(missing, CompileError::MissingReturn(ref name, _) if name == "f"));
+        assert_eq!(
+            missing.to_string(),
+            "1:10: function `f` does not return a value on every path"
+        );
+        let input = "function f(c: bool) { if c { return 1; } else { return 2; } }
+            function g() { return 1; 2; }
+            let x = f(false) + g();";
+        assert_eq!(check_source(input), Ok(()));
+        assert!(matches!(
+            error("for i in false..true { }"),
+            CompileError::TypeMismatch { .. }
+        ));
+        assert!(matches!(
+            error("if 1 { }"),
+            CompileError::TypeMismatch { .. }
+        ));
+        assert!(matches!(
+            error("let x: u8 = 256;"),
+            CompileError::LiteralOutOfRange { .. }
+        ));
+        assert!(matches!(
+            error("true + false;"),
+            CompileError::InvalidOperand { .. }
+        ));
+        assert!(matches!(
+            error("let a = [1, 2]; a[2];"),
+            CompileError::IndexOutOfBounds { len: 2, .. }
+        ));
+        assert!(matches!(
+            error("let (a, b) = (1, 2, 3);"),
+            CompileError::PatternMismatch { len: 2, .. }
+        ));
+        assert!(matches!(error("let a = [];"), CompileError::EmptyArray(_)));
+        assert!(matches!(
+            error("let p = 1; p.x;"),
+            CompileError::NotAStruct { .. }
+        ));
+        assert!(matches!(
+            error("function f() { return f(); }"),
+            CompileError::RecursiveFunction(..)
+        ));
+    }
+}
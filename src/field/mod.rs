@@ -0,0 +1,147 @@
+use std::{fmt, sync::OnceLock};
+
+use num_bigint::{BigInt, Sign};
+use num_traits::{One, Zero};
+
+/// A prime field. Elements are `BigInt`s in `0..modulus`; every operation
+/// takes reduced operands and returns a reduced result.
+pub trait Field: fmt::Debug {
+    fn name(&self) -> &'static str;
+
+    fn modulus(&self) -> &BigInt;
+
+    /// The element congruent to `value`, which may be negative or too large.
+    fn reduce(&self, value: &BigInt) -> BigInt {
+        let modulus = self.modulus();
+        let value = value % modulus;
+        if value.sign() == Sign::Minus {
+            value + modulus
+        } else {
+            value
+        }
+    }
+
+    fn add(&self, lhs: &BigInt, rhs: &BigInt) -> BigInt {
+        self.reduce(&(lhs + rhs))
+    }
+
+    fn sub(&self, lhs: &BigInt, rhs: &BigInt) -> BigInt {
+        self.reduce(&(lhs - rhs))
+    }
+
+    fn mul(&self, lhs: &BigInt, rhs: &BigInt) -> BigInt {
+        self.reduce(&(lhs * rhs))
+    }
+
+    fn neg(&self, value: &BigInt) -> BigInt {
+        self.reduce(&-value)
+    }
+
+    /// `None` for zero, which has no inverse.
+    fn inverse(&self, value: &BigInt) -> Option<BigInt> {
+        if value.is_zero() {
+            return None;
+        }
+        // Fermat: value^(p - 2) * value = value^(p - 1) = 1
+        let exponent = self.modulus() - 2;
+        Some(value.modpow(&exponent, self.modulus()))
+    }
+
+    /// `None` if `rhs` is zero.
+    fn div(&self, lhs: &BigInt, rhs: &BigInt) -> Option<BigInt> {
+        self.inverse(rhs).map(|inverse| self.mul(lhs, &inverse))
+    }
+
+    /// Whether `value` is already reduced.
+    fn contains(&self, value: &BigInt) -> bool {
+        value.sign() != Sign::Minus && value < self.modulus()
+    }
+}
+
+fn parse(digits: &str) -> BigInt {
+    BigInt::parse_bytes(digits.as_bytes(), 10).expect("modulus is a decimal literal")
+}
+
+/// The scalar field of the BN254 curve, used by Groth16 and PLONK on Ethereum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bn254;
+
+impl Field for Bn254 {
+    fn name(&self) -> &'static str {
+        "bn254"
+    }
+
+    fn modulus(&self) -> &BigInt {
+        static MODULUS: OnceLock<BigInt> = OnceLock::new();
+        MODULUS.get_or_init(|| {
+            parse("21888242871839275222246405745257275088548364400416034343698204186575808495617")
+        })
+    }
+}
+
+/// `2^64 - 2^32 + 1`, used by Plonky2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Goldilocks;
+
+impl Field for Goldilocks {
+    fn name(&self) -> &'static str {
+        "goldilocks"
+    }
+
+    fn modulus(&self) -> &BigInt {
+        static MODULUS: OnceLock<BigInt> = OnceLock::new();
+        MODULUS.get_or_init(|| (BigInt::one() << 64) - (BigInt::one() << 32) + 1)
+    }
+}
+
+/// `2^31 - 2^27 + 1`, used by RISC Zero and Plonky3.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BabyBear;
+
+impl Field for BabyBear {
+    fn name(&self) -> &'static str {
+        "babybear"
+    }
+
+    fn modulus(&self) -> &BigInt {
+        static MODULUS: OnceLock<BigInt> = OnceLock::new();
+        MODULUS.get_or_init(|| (BigInt::one() << 31) - (BigInt::one() << 27) + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use super::{BabyBear, Bn254, Field, Goldilocks};
+
+    #[test]
+    fn test_field_arithmetic() {
+        let fields: [&dyn Field; 3] = [&Bn254, &Goldilocks, &BabyBear];
+        for field in fields {
+            let p = field.modulus();
+            let minus_one = p - 1;
+            let (two, three) = (BigInt::from(2), BigInt::from(3));
+
+            assert_eq!(
+                field.reduce(&BigInt::from(-1)),
+                minus_one,
+                "{}",
+                field.name()
+            );
+            assert_eq!(field.add(&minus_one, &two), BigInt::from(1));
+            assert_eq!(field.sub(&two, &three), minus_one);
+            assert_eq!(field.mul(&minus_one, &minus_one), BigInt::from(1));
+            assert_eq!(field.neg(&BigInt::from(0)), BigInt::from(0));
+
+            let half = field.inverse(&two).unwrap();
+            assert_eq!(field.mul(&half, &two), BigInt::from(1));
+            assert_eq!(field.div(&three, &three), Some(BigInt::from(1)));
+            assert_eq!(field.div(&three, &BigInt::from(0)), None);
+            assert!(!field.contains(p));
+        }
+
+        assert_eq!(Goldilocks.modulus(), &BigInt::from(18446744069414584321u64));
+        assert_eq!(BabyBear.modulus(), &BigInt::from(2013265921));
+    }
+}
@@ -1,11 +1,18 @@
+use std::collections::BTreeMap;
+
 use super::ast::ast;
+use crate::token::token::Span;
 use num_bigint::BigInt;
 
-// https://github.com/michaeljclark/michaeljclark.github.io/blob/master/asm.md#assembler-pseudo-instructions
-// TODO: Register based ir, vm
+pub mod register;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
     Nop,
+    Push {
+        value: Constant,
+    },
+    Pop,
     BinOp {
         op: ast::BinaryOperator,
         // type: Type,
@@ -14,20 +21,79 @@ pub enum Instruction {
         op: ast::UnaryOperator,
         // type: Type,
     },
+    /// Pops `rhs`, then `lhs`, and pushes the integer quotient of their
+    /// representatives. `BinOp` with `Divide` is field division.
+    IntegerDivide,
     Swap,
     Dup,
     // If there is a memory,
+    /// Pops an address and pushes the value stored there.
     Load,
+    /// Pops an address, then pops the value to store at it.
     Store,
+    /// Runs `name` in a new frame. Arguments are on the stack with the last one
+    /// on top; the callee leaves its return value in their place.
+    Call {
+        name: String,
+    },
+    /// Pops `len` values, the last element on top, and pushes them as an array.
+    MakeArray {
+        len: usize,
+    },
+    /// Pops an array and pushes its element at a constant `index`.
+    Get {
+        index: usize,
+    },
+    /// Pops an index, then an array, and pushes the selected element. In a
+    /// circuit this is a one-hot selection over the elements. Lowering
+    /// constrains the index to the array's range before it, with a
+    /// `RangeCheck` and, unless the length is a power of two, an `Assert`.
+    Select,
+    /// Pops `len` values, the last element on top, and pushes them as a tuple.
+    MakeTuple {
+        len: usize,
+    },
+    /// Pops a tuple of `len` elements and pushes the elements, the last on top.
+    Unpack {
+        len: usize,
+    },
+    /// Pops one value per field, the last field on top, and pushes the struct.
+    MakeStruct {
+        name: String,
+        fields: Vec<String>,
+    },
+    /// Pops a boolean and fails with the message unless it is true. `span` is
+    /// the `assert` statement, for error reporting.
+    Assert {
+        message: Option<String>,
+        span: Span,
+    },
+    /// Pops a value and appends it to the public output journal.
+    Commit,
+    /// Pushes the next value of the public or private input tape.
+    Read {
+        visibility: ast::Visibility,
+    },
+    /// Fails unless the value on top of the stack is in `0..2^bits`, leaving
+    /// it in place. `bits: 1` is the boolean constraint.
+    RangeCheck {
+        bits: u32,
+    },
+    /// Pops a struct and pushes the value of its field `name`.
+    GetField {
+        name: String,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Operand {
     Constant(Constant),
+    /// The value this far below the top of the data stack, 0 being the top
     StackValue(usize),
     Register(Register),
 }
 
+// https://github.com/michaeljclark/michaeljclark.github.io/blob/master/asm.md#assembler-pseudo-instructions
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum Register {
     Zero,
@@ -54,29 +120,92 @@ pub enum Constant {
     Num { value: BigInt },
 }
 
+/// Index of a block in `Program::blocks`.
+pub type BlockId = usize;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Block {
     pub instructions: Vec<Instruction>,
     pub exit: BlockExit,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+/// How a block ends. Targets are fixed, so the control flow graph can be
+/// read off the exits without running anything.
+///
+/// An exit first reads its operands. Stack operands are then popped, down to
+/// the deepest one read, so `StackValue(0)` consumes the top of the stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BlockExit {
-    Jump,
-    ConditionalJump,
-    Return,
+    /// Continues in `target` with `args` pushed in order, the last on top.
+    Jump {
+        target: BlockId,
+        args: Vec<Operand>,
+    },
+    /// `cond` has to be a boolean.
+    ConditionalJump {
+        cond: Operand,
+        then: JumpArg,
+        else_: JumpArg,
+    },
+    /// Pops the return address and pushes `value` for the caller. Returning
+    /// from the outermost frame ends the program. `None` returns nothing,
+    /// which only top-level code that ends without `return` does.
+    Return {
+        value: Option<Operand>,
+    },
     Unreachable,
 }
 
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub struct JumpArg {
-//     pub args: Vec<Operand>,
-// }
+/// A jump to `target` passing `args`, as in `BlockExit::Jump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpArg {
+    pub target: BlockId,
+    pub args: Vec<Operand>,
+}
+
+impl BlockExit {
+    /// Blocks control can go to next, within the same function.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            BlockExit::Jump { target, .. } => vec![*target],
+            BlockExit::ConditionalJump { then, else_, .. } => vec![then.target, else_.target],
+            BlockExit::Return { .. } | BlockExit::Unreachable => vec![],
+        }
+    }
+
+    /// Every operand the exit reads.
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            BlockExit::Jump { args, .. } => args.iter().collect(),
+            BlockExit::ConditionalJump { cond, then, else_ } => std::iter::once(cond)
+                .chain(&then.args)
+                .chain(&else_.args)
+                .collect(),
+            BlockExit::Return { value } => value.iter().collect(),
+            BlockExit::Unreachable => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDefinition {
+    pub params: usize,
+    /// Execution starts at block 0
+    pub blocks: Vec<Block>,
+}
 
-// TODO: if function declaration is added, Program should have decls
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Program {
-    /// Blocks
+    /// Blocks of the top-level code
     /// Can be changed to BTreeMap<BlockId, Block>
     pub blocks: Vec<Block>,
+    pub functions: BTreeMap<String, FunctionDefinition>,
+    /// Declared inputs, in the order they are read
+    pub inputs: Vec<Input>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub name: String,
+    pub visibility: ast::Visibility,
 }
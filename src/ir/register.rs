@@ -0,0 +1,621 @@
+use std::collections::{BTreeMap, HashMap};
+
+use thiserror::Error;
+
+use super::{ast, BlockExit, BlockId, Input, JumpArg, Operand, Register, RegisterType};
+use crate::token::token::Span;
+
+/// Three-address form of the IR: each instruction reads `Operand`s and writes
+/// its result to a register, instead of going through the data stack.
+///
+/// Registers belong to a function call. A block's parameters are
+/// `Saved(_, 0)`, `Saved(_, 1)`, ..., set from the jump that enters it; a
+/// function's are set by the `Call`. Everything else lives in `Temp`s, each
+/// written once. Exits are `ir::BlockExit`s over register operands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// `dst = lhs op rhs`
+    BinOp {
+        op: ast::BinaryOperator,
+        dst: Register,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// `dst = op src`
+    UnaryOp {
+        op: ast::UnaryOperator,
+        dst: Register,
+        src: Operand,
+    },
+    IntegerDivide {
+        dst: Register,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Load {
+        dst: Register,
+        address: Operand,
+    },
+    Store {
+        address: Operand,
+        src: Operand,
+    },
+    Call {
+        name: String,
+        dst: Register,
+        args: Vec<Operand>,
+    },
+    MakeArray {
+        dst: Register,
+        elements: Vec<Operand>,
+    },
+    Get {
+        dst: Register,
+        src: Operand,
+        index: usize,
+    },
+    Select {
+        dst: Register,
+        array: Operand,
+        index: Operand,
+    },
+    MakeTuple {
+        dst: Register,
+        elements: Vec<Operand>,
+    },
+    /// Writes the elements of the tuple `src` to `dsts`, in order.
+    Unpack {
+        dsts: Vec<Register>,
+        src: Operand,
+    },
+    MakeStruct {
+        dst: Register,
+        name: String,
+        fields: Vec<String>,
+        values: Vec<Operand>,
+    },
+    Assert {
+        cond: Operand,
+        message: Option<String>,
+        span: Span,
+    },
+    Commit {
+        src: Operand,
+    },
+    Read {
+        dst: Register,
+        visibility: ast::Visibility,
+    },
+    RangeCheck {
+        src: Operand,
+        bits: u32,
+    },
+    GetField {
+        dst: Register,
+        src: Operand,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+    pub exit: BlockExit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDefinition {
+    pub params: usize,
+    /// Execution starts at block 0
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub blocks: Vec<Block>,
+    pub functions: BTreeMap<String, FunctionDefinition>,
+    pub inputs: Vec<Input>,
+}
+
+/// The register holding parameter `index` of a block or function.
+pub fn param(index: usize) -> Register {
+    Register::Saved(RegisterType::Integer, index)
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum TranslateError {
+    #[error("block {block} pops more values than its stack holds")]
+    StackUnderflow { block: BlockId },
+    #[error("undefined function `{0}`")]
+    UndefinedFunction(String),
+    #[error("block {block} is entered with different stack depths")]
+    StackMismatch { block: BlockId },
+    #[error("the stack IR cannot have register operands")]
+    RegisterOperand,
+}
+
+/// Translates a stack IR program into the register IR.
+///
+/// Each block is run symbolically over a stack of operands: pushing a
+/// constant, `Dup`, `Swap` and `Pop` only rearrange that stack, and every
+/// other instruction reads its operands from it and pushes the register it
+/// writes. Whatever is on the stack at a jump is passed to the target as
+/// block arguments. Blocks that no exit reaches are left `Unreachable`.
+pub fn translate(program: &super::Program) -> Result<Program, TranslateError> {
+    let arities: HashMap<&str, usize> = program
+        .functions
+        .iter()
+        .map(|(name, function)| (name.as_str(), function.params))
+        .collect();
+
+    let mut functions = BTreeMap::new();
+    for (name, function) in &program.functions {
+        let blocks = Translator::new(&arities).blocks(&function.blocks, function.params)?;
+        functions.insert(
+            name.clone(),
+            FunctionDefinition {
+                params: function.params,
+                blocks,
+            },
+        );
+    }
+
+    Ok(Program {
+        blocks: Translator::new(&arities).blocks(&program.blocks, 0)?,
+        functions,
+        inputs: program.inputs.clone(),
+    })
+}
+
+struct Translator<'a> {
+    arities: &'a HashMap<&'a str, usize>,
+    next_temp: usize,
+    /// The block being translated
+    block: BlockId,
+    stack: Vec<Operand>,
+    instructions: Vec<Instruction>,
+}
+
+impl<'a> Translator<'a> {
+    fn new(arities: &'a HashMap<&'a str, usize>) -> Self {
+        Translator {
+            arities,
+            next_temp: 0,
+            block: 0,
+            stack: vec![],
+            instructions: vec![],
+        }
+    }
+
+    /// Translates the blocks of one function, which starts with `params`
+    /// values on the stack.
+    fn blocks(
+        &mut self,
+        blocks: &[super::Block],
+        params: usize,
+    ) -> Result<Vec<Block>, TranslateError> {
+        let mut translated: Vec<Block> = blocks
+            .iter()
+            .map(|_| Block {
+                instructions: vec![],
+                exit: BlockExit::Unreachable,
+            })
+            .collect();
+        // Stack depth on entry, for every block reached so far
+        let mut depths: Vec<Option<usize>> = vec![None; blocks.len()];
+        let mut pending = vec![];
+        if !blocks.is_empty() {
+            depths[0] = Some(params);
+            pending.push(0);
+        }
+
+        while let Some(id) = pending.pop() {
+            self.block = id;
+            self.stack = (0..depths[id].unwrap_or(0))
+                .map(|index| Operand::Register(param(index)))
+                .collect();
+            for instruction in &blocks[id].instructions {
+                self.instruction(instruction)?;
+            }
+            let exit = self.exit(&blocks[id].exit)?;
+
+            let jumps = match &exit {
+                BlockExit::Jump { target, args } => vec![(*target, args.len())],
+                BlockExit::ConditionalJump { then, else_, .. } => vec![
+                    (then.target, then.args.len()),
+                    (else_.target, else_.args.len()),
+                ],
+                BlockExit::Return { .. } | BlockExit::Unreachable => vec![],
+            };
+            // A target out of range is left for the VM to report.
+            for (target, depth) in jumps {
+                match depths.get(target) {
+                    Some(None) => {
+                        depths[target] = Some(depth);
+                        pending.push(target);
+                    }
+                    Some(Some(other)) if *other != depth => {
+                        return Err(TranslateError::StackMismatch { block: target })
+                    }
+                    _ => {}
+                }
+            }
+
+            translated[id] = Block {
+                instructions: std::mem::take(&mut self.instructions),
+                exit,
+            };
+        }
+        Ok(translated)
+    }
+
+    fn temp(&mut self) -> Register {
+        let register = Register::Temp(RegisterType::Integer, self.next_temp);
+        self.next_temp += 1;
+        register
+    }
+
+    fn pop(&mut self) -> Result<Operand, TranslateError> {
+        self.stack
+            .pop()
+            .ok_or(TranslateError::StackUnderflow { block: self.block })
+    }
+
+    /// Pops the top `len` operands, keeping their stack order.
+    fn pop_operands(&mut self, len: usize) -> Result<Vec<Operand>, TranslateError> {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(len)
+            .ok_or(TranslateError::StackUnderflow { block: self.block })?;
+        Ok(self.stack.split_off(start))
+    }
+
+    /// Emits an instruction writing a fresh register, and pushes that register.
+    fn emit_to(&mut self, instruction: impl FnOnce(Register) -> Instruction) {
+        let dst = self.temp();
+        self.instructions.push(instruction(dst));
+        self.stack.push(Operand::Register(dst));
+    }
+
+    fn instruction(&mut self, instruction: &super::Instruction) -> Result<(), TranslateError> {
+        use super::Instruction as Stack;
+
+        match instruction {
+            Stack::Nop => {}
+            Stack::Push { value } => self.stack.push(Operand::Constant(value.clone())),
+            Stack::Pop => {
+                self.pop()?;
+            }
+            Stack::Swap => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.extend([a, b]);
+            }
+            Stack::Dup => {
+                let top = self.pop()?;
+                self.stack.extend([top.clone(), top]);
+            }
+            Stack::BinOp { op } => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.emit_to(|dst| Instruction::BinOp {
+                    op: op.clone(),
+                    dst,
+                    lhs,
+                    rhs,
+                });
+            }
+            Stack::UnaryOp { op } => {
+                let src = self.pop()?;
+                self.emit_to(|dst| Instruction::UnaryOp {
+                    op: op.clone(),
+                    dst,
+                    src,
+                });
+            }
+            Stack::IntegerDivide => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.emit_to(|dst| Instruction::IntegerDivide { dst, lhs, rhs });
+            }
+            Stack::Load => {
+                let address = self.pop()?;
+                self.emit_to(|dst| Instruction::Load { dst, address });
+            }
+            Stack::Store => {
+                let address = self.pop()?;
+                let src = self.pop()?;
+                self.instructions.push(Instruction::Store { address, src });
+            }
+            Stack::Call { name } => {
+                let params = *self
+                    .arities
+                    .get(name.as_str())
+                    .ok_or_else(|| TranslateError::UndefinedFunction(name.clone()))?;
+                let args = self.pop_operands(params)?;
+                self.emit_to(|dst| Instruction::Call {
+                    name: name.clone(),
+                    dst,
+                    args,
+                });
+            }
+            Stack::MakeArray { len } => {
+                let elements = self.pop_operands(*len)?;
+                self.emit_to(|dst| Instruction::MakeArray { dst, elements });
+            }
+            Stack::Get { index } => {
+                let src = self.pop()?;
+                self.emit_to(|dst| Instruction::Get {
+                    dst,
+                    src,
+                    index: *index,
+                });
+            }
+            Stack::Select => {
+                let index = self.pop()?;
+                let array = self.pop()?;
+                self.emit_to(|dst| Instruction::Select { dst, array, index });
+            }
+            Stack::MakeTuple { len } => {
+                let elements = self.pop_operands(*len)?;
+                self.emit_to(|dst| Instruction::MakeTuple { dst, elements });
+            }
+            Stack::Unpack { len } => {
+                let src = self.pop()?;
+                let dsts: Vec<Register> = (0..*len).map(|_| self.temp()).collect();
+                self.stack
+                    .extend(dsts.iter().map(|dst| Operand::Register(*dst)));
+                self.instructions.push(Instruction::Unpack { dsts, src });
+            }
+            Stack::MakeStruct { name, fields } => {
+                let values = self.pop_operands(fields.len())?;
+                self.emit_to(|dst| Instruction::MakeStruct {
+                    dst,
+                    name: name.clone(),
+                    fields: fields.clone(),
+                    values,
+                });
+            }
+            Stack::Assert { message, span } => {
+                let cond = self.pop()?;
+                self.instructions.push(Instruction::Assert {
+                    cond,
+                    message: message.clone(),
+                    span: *span,
+                });
+            }
+            Stack::Commit => {
+                let src = self.pop()?;
+                self.instructions.push(Instruction::Commit { src });
+            }
+            Stack::Read { visibility } => {
+                self.emit_to(|dst| Instruction::Read {
+                    dst,
+                    visibility: *visibility,
+                });
+            }
+            // The value stays on the stack.
+            Stack::RangeCheck { bits } => {
+                let src = self
+                    .stack
+                    .last()
+                    .cloned()
+                    .ok_or(TranslateError::StackUnderflow { block: self.block })?;
+                self.instructions
+                    .push(Instruction::RangeCheck { src, bits: *bits });
+            }
+            Stack::GetField { name } => {
+                let src = self.pop()?;
+                self.emit_to(|dst| Instruction::GetField {
+                    dst,
+                    src,
+                    name: name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The operand a stack exit reads, in terms of the symbolic stack.
+    fn operand(&self, operand: &Operand) -> Result<Operand, TranslateError> {
+        match operand {
+            Operand::Constant(_) => Ok(operand.clone()),
+            Operand::StackValue(depth) => self
+                .stack
+                .len()
+                .checked_sub(depth + 1)
+                .map(|index| self.stack[index].clone())
+                .ok_or(TranslateError::StackUnderflow { block: self.block }),
+            Operand::Register(_) => Err(TranslateError::RegisterOperand),
+        }
+    }
+
+    /// Translates a stack exit. Jumps pass the whole remaining stack, with
+    /// the exit's own arguments on top, as the target's parameters.
+    fn exit(&mut self, exit: &BlockExit) -> Result<BlockExit, TranslateError> {
+        let operands = exit
+            .operands()
+            .into_iter()
+            .map(|operand| self.operand(operand))
+            .collect::<Result<Vec<_>, _>>()?;
+        let depth = exit
+            .operands()
+            .into_iter()
+            .filter_map(|operand| match operand {
+                Operand::StackValue(depth) => Some(depth + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        self.stack.truncate(self.stack.len() - depth);
+
+        let jump = |target: BlockId, args: &[Operand]| JumpArg {
+            target,
+            args: self.stack.iter().chain(args).cloned().collect(),
+        };
+        Ok(match exit {
+            BlockExit::Jump { target, .. } => {
+                let JumpArg { target, args } = jump(*target, &operands);
+                BlockExit::Jump { target, args }
+            }
+            BlockExit::ConditionalJump { then, else_, .. } => {
+                let (then_args, else_args) = operands[1..].split_at(then.args.len());
+                BlockExit::ConditionalJump {
+                    cond: operands[0].clone(),
+                    then: jump(then.target, then_args),
+                    else_: jump(else_.target, else_args),
+                }
+            }
+            BlockExit::Return { .. } => BlockExit::Return {
+                value: operands.into_iter().next(),
+            },
+            BlockExit::Unreachable => BlockExit::Unreachable,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{param, translate, Instruction, TranslateError};
+    use crate::ast::ast::{BinaryOperator, Visibility};
+    use crate::compiler::compile_source;
+    use crate::ir::{self, BlockExit, Constant, JumpArg, Operand, Program, Register, RegisterType};
+
+    fn temp(index: usize) -> Operand {
+        Operand::Register(Register::Temp(RegisterType::Integer, index))
+    }
+
+    fn num(value: i64) -> Operand {
+        Operand::Constant(Constant::Num {
+            value: value.into(),
+        })
+    }
+
+    #[test]
+    fn test_translate() {
+        let program = translate(&compile_source("private x: field; return x * 3 + 1;")).unwrap();
+        let dst = |index| Register::Temp(RegisterType::Integer, index);
+        assert_eq!(
+            program.blocks[0].instructions,
+            vec![
+                Instruction::Read {
+                    dst: dst(0),
+                    visibility: Visibility::Private
+                },
+                Instruction::Store {
+                    address: num(0),
+                    src: temp(0)
+                },
+                Instruction::Load {
+                    dst: dst(1),
+                    address: num(0)
+                },
+                Instruction::BinOp {
+                    op: BinaryOperator::Multiply,
+                    dst: dst(2),
+                    lhs: temp(1),
+                    rhs: num(3)
+                },
+                Instruction::BinOp {
+                    op: BinaryOperator::Plus,
+                    dst: dst(3),
+                    lhs: temp(2),
+                    rhs: num(1)
+                },
+                Instruction::Commit { src: temp(3) },
+            ]
+        );
+        assert_eq!(
+            program.blocks[0].exit,
+            BlockExit::Return {
+                value: Some(temp(3))
+            }
+        );
+
+        // The 5 is still on the stack across the branch, so it is passed to
+        // both branches and on to the join with the branch's value.
+        let program = translate(&compile_source(
+            "let y = 5 + if true { 1 } else { 2 }; return y;",
+        ))
+        .unwrap();
+        let saved = |index| Operand::Register(param(index));
+        assert_eq!(
+            program.blocks[0].exit,
+            BlockExit::ConditionalJump {
+                cond: num(1),
+                then: JumpArg {
+                    target: 1,
+                    args: vec![num(5)]
+                },
+                else_: JumpArg {
+                    target: 2,
+                    args: vec![num(5)]
+                },
+            }
+        );
+        assert_eq!(
+            program.blocks[1].exit,
+            BlockExit::Jump {
+                target: 3,
+                args: vec![saved(0), num(1)]
+            }
+        );
+        assert!(matches!(
+            &program.blocks[3].instructions[0],
+            Instruction::BinOp { lhs, rhs, .. } if *lhs == saved(0) && *rhs == saved(1)
+        ));
+    }
+
+    #[test]
+    fn test_translate_errors() {
+        let block = |instructions, exit| ir::Block { instructions, exit };
+        let program = |blocks| Program {
+            blocks,
+            ..Default::default()
+        };
+
+        let underflow = program(vec![block(
+            vec![ir::Instruction::Pop],
+            BlockExit::Unreachable,
+        )]);
+        assert_eq!(
+            translate(&underflow).unwrap_err(),
+            TranslateError::StackUnderflow { block: 0 }
+        );
+
+        let call = ir::Instruction::Call {
+            name: "f".to_string(),
+        };
+        let undefined = program(vec![block(vec![call], BlockExit::Unreachable)]);
+        assert_eq!(
+            translate(&undefined).unwrap_err(),
+            TranslateError::UndefinedFunction("f".to_string())
+        );
+
+        let push = ir::Instruction::Push {
+            value: Constant::Unit,
+        };
+        let mismatch = program(vec![
+            block(
+                vec![push],
+                BlockExit::ConditionalJump {
+                    cond: Operand::StackValue(0),
+                    then: JumpArg {
+                        target: 1,
+                        args: vec![],
+                    },
+                    else_: JumpArg {
+                        target: 1,
+                        args: vec![num(1)],
+                    },
+                },
+            ),
+            block(vec![], BlockExit::Return { value: None }),
+        ]);
+        assert_eq!(
+            translate(&mismatch).unwrap_err(),
+            TranslateError::StackMismatch { block: 1 }
+        );
+    }
+}
@@ -1,10 +1,42 @@
-use crate::token::token::Token;
+use std::fmt;
+
+use num_bigint::BigInt;
+
+use crate::token::token::{Span, SpannedToken, Token};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexError {
+    UnexpectedCharacter(char),
+    UnterminatedComment,
+    MalformedNumber(String),
+    UnterminatedString,
+    InvalidEscape(char),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::UnexpectedCharacter(ch) => write!(f, "unexpected character {:?}", ch),
+            LexError::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexError::MalformedNumber(literal) => {
+                write!(f, "malformed integer literal `{}`", literal)
+            }
+            LexError::UnterminatedString => write!(f, "unterminated string literal"),
+            LexError::InvalidEscape(ch) => write!(f, "invalid escape sequence `\\{}`", ch),
+        }
+    }
+}
 
 pub struct Lexer<'a> {
     input: &'a str,
     position: usize,
     read_position: usize,
     ch: u8,
+    // 1-based line and column of `ch`
+    line: usize,
+    column: usize,
+    // Added to every span's byte range
+    offset: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -14,13 +46,30 @@ impl<'a> Lexer<'a> {
             position: 0,
             read_position: 0,
             ch: 0,
+            line: 1,
+            column: 0,
+            offset: 0,
         };
 
         lexer.read_char();
         lexer
     }
 
+    /// Starts span byte ranges at `offset` instead of 0, so that spans from
+    /// different files don't overlap. Lines and columns are unaffected.
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
     fn read_char(&mut self) {
+        if self.ch == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
         if self.read_position >= self.input.len() {
             self.ch = 0;
         } else {
@@ -31,9 +80,27 @@ impl<'a> Lexer<'a> {
         self.read_position += 1;
     }
 
-    pub fn next_token(&mut self) -> Token {
+    pub fn next_token(&mut self) -> SpannedToken {
         self.skip_whitespace();
 
+        let start = self.position.min(self.input.len());
+        let line = self.line;
+        let column = self.column;
+        let token = self.read_token();
+        let end = self.position.min(self.input.len());
+
+        SpannedToken {
+            token,
+            span: Span {
+                start: start + self.offset,
+                end: end + self.offset,
+                line,
+                column,
+            },
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
         let tok = match self.ch {
             b'=' => {
                 if self.peek_char() == b'=' {
@@ -43,8 +110,22 @@ impl<'a> Lexer<'a> {
                     Token::Assign
                 }
             }
-            b'+' => Token::Plus,
-            b'-' => Token::Minus,
+            b'+' => {
+                if self.peek_char() == b'=' {
+                    self.read_char();
+                    Token::PlusAssign
+                } else {
+                    Token::Plus
+                }
+            }
+            b'-' => {
+                if self.peek_char() == b'=' {
+                    self.read_char();
+                    Token::MinusAssign
+                } else {
+                    Token::Minus
+                }
+            }
             b'!' => {
                 if self.peek_char() == b'=' {
                     self.read_char();
@@ -53,24 +134,98 @@ impl<'a> Lexer<'a> {
                     Token::Bang
                 }
             }
-            b'*' => Token::Asterisk,
-            b'/' => Token::Slash,
-            b'<' => Token::LessThan,
-            b'>' => Token::GreaterThan,
+            b'*' => {
+                if self.peek_char() == b'=' {
+                    self.read_char();
+                    Token::AsteriskAssign
+                } else {
+                    Token::Asterisk
+                }
+            }
+            b'%' => Token::Percent,
+            b'^' => Token::Caret,
+            b'&' => {
+                if self.peek_char() == b'&' {
+                    self.read_char();
+                    Token::And
+                } else {
+                    Token::Ampersand
+                }
+            }
+            b'|' => {
+                if self.peek_char() == b'|' {
+                    self.read_char();
+                    Token::Or
+                } else {
+                    Token::Pipe
+                }
+            }
+            b'/' => match self.peek_char() {
+                b'/' => return self.read_line_comment(),
+                b'*' => return self.read_block_comment(),
+                _ => Token::Slash,
+            },
+            b'<' => match self.peek_char() {
+                b'=' => {
+                    self.read_char();
+                    Token::LessEqual
+                }
+                b'<' => {
+                    self.read_char();
+                    Token::ShiftLeft
+                }
+                _ => Token::LessThan,
+            },
+            b'>' => match self.peek_char() {
+                b'=' => {
+                    self.read_char();
+                    Token::GreaterEqual
+                }
+                b'>' => {
+                    self.read_char();
+                    Token::ShiftRight
+                }
+                _ => Token::GreaterThan,
+            },
+            b'.' => {
+                if self.peek_char() == b'.' {
+                    self.read_char();
+                    Token::DotDot
+                } else {
+                    Token::Dot
+                }
+            }
             b',' => Token::Comma,
+            b':' => {
+                if self.peek_char() == b':' {
+                    self.read_char();
+                    Token::ColonColon
+                } else {
+                    Token::Colon
+                }
+            }
             b';' => Token::Semicolon,
             b'(' => Token::Lparen,
             b')' => Token::Rparen,
             b'{' => Token::Lbrace,
             b'}' => Token::Rbrace,
+            b'[' => Token::Lbracket,
+            b']' => Token::Rbracket,
             b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
                 return self.read_identifier();
             }
             b'0'..=b'9' => {
                 return self.read_number();
             }
+            b'"' => return self.read_string(),
             0 => Token::Eof,
-            _ => Token::Illegal,
+            _ => {
+                let ch = self.input[self.position..].chars().next().unwrap_or('\0');
+                for _ in 1..ch.len_utf8() {
+                    self.read_char();
+                }
+                Token::Illegal(LexError::UnexpectedCharacter(ch))
+            }
         };
 
         self.read_char();
@@ -79,19 +234,12 @@ impl<'a> Lexer<'a> {
     }
 
     fn skip_whitespace(&mut self) {
-        loop {
-            match self.ch {
-                b' ' | b'\t' | b'\n' | b'\r' => {
-                    self.read_char();
-                }
-                _ => {
-                    break;
-                }
-            }
+        while matches!(self.ch, b' ' | b'\t' | b'\n' | b'\r') {
+            self.read_char();
         }
     }
 
-    fn peek_char(&mut self) -> u8 {
+    fn peek_char(&self) -> u8 {
         if self.read_position >= self.input.len() {
             0
         } else {
@@ -99,18 +247,91 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn read_identifier(&mut self) -> Token {
+    fn read_line_comment(&mut self) -> Token {
         let position = self.position;
 
+        while !matches!(self.ch, b'\n' | 0) {
+            self.read_char();
+        }
+
+        let literal = &self.input[position..self.position];
+
+        // `////...` is a plain comment, as in Rust.
+        match literal.strip_prefix("///") {
+            Some(doc) if !doc.starts_with('/') => Token::DocComment(String::from(doc)),
+            _ => Token::Comment(String::from(literal)),
+        }
+    }
+
+    fn read_block_comment(&mut self) -> Token {
+        let position = self.position;
+        let mut depth = 0;
+
         loop {
-            match self.ch {
-                b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+            match (self.ch, self.peek_char()) {
+                (b'/', b'*') => {
+                    depth += 1;
                     self.read_char();
                 }
-                _ => {
-                    break;
+                (b'*', b'/') => {
+                    depth -= 1;
+                    self.read_char();
+                    if depth == 0 {
+                        self.read_char();
+                        break;
+                    }
                 }
+                (0, _) => return Token::Illegal(LexError::UnterminatedComment),
+                _ => {}
             }
+            self.read_char();
+        }
+
+        Token::Comment(String::from(&self.input[position..self.position]))
+    }
+
+    /// Reads a string literal. `\"`, `\\`, `\n` and `\t` are the only escapes.
+    fn read_string(&mut self) -> Token {
+        let mut bytes = vec![];
+        let mut error = None;
+
+        loop {
+            self.read_char();
+            match self.ch {
+                b'"' => break,
+                0 => return Token::Illegal(LexError::UnterminatedString),
+                b'\\' => {
+                    self.read_char();
+                    match self.ch {
+                        b'"' => bytes.push(b'"'),
+                        b'\\' => bytes.push(b'\\'),
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        0 => return Token::Illegal(LexError::UnterminatedString),
+                        _ => {
+                            let ch = self.input[self.position..].chars().next().unwrap_or('\0');
+                            // Keep going so the rest of the literal isn't lexed as code.
+                            error.get_or_insert(LexError::InvalidEscape(ch));
+                        }
+                    }
+                }
+                ch => bytes.push(ch),
+            }
+        }
+        self.read_char();
+
+        match error {
+            Some(error) => Token::Illegal(error),
+            // Only ASCII bytes were replaced, so this is still valid UTF-8.
+            None => Token::Str(String::from_utf8(bytes).expect("input is a str")),
+        }
+    }
+
+    fn read_identifier(&mut self) -> Token {
+        let position = self.position;
+
+        while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
+            self.read_char();
         }
 
         let literal = &self.input[position..self.position];
@@ -118,31 +339,160 @@ impl<'a> Lexer<'a> {
         match literal {
             "function" => Token::Function,
             "let" => Token::Let,
+            "mut" => Token::Mut,
             "true" => Token::Bool(true),
             "false" => Token::Bool(false),
             "if" => Token::If,
             "else" => Token::Else,
             "return" => Token::Return,
             "assert" => Token::Assert,
+            "commit" => Token::Commit,
+            "for" => Token::For,
+            "in" => Token::In,
+            "struct" => Token::Struct,
+            "const" => Token::Const,
+            "import" => Token::Import,
+            "public" => Token::Public,
+            "private" => Token::Private,
             _ => Token::Ident(String::from(literal)),
         }
     }
 
+    /// Reads a decimal, `0x`, `0o` or `0b` literal with optional `_` separators.
     fn read_number(&mut self) -> Token {
         let position = self.position;
 
+        // Swallow trailing letters too, so `12ab` is one malformed literal rather
+        // than a number followed by an identifier.
+        while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
+            self.read_char();
+        }
+
+        let literal = &self.input[position..self.position];
+        let (radix, digits) = match literal.get(..2) {
+            Some("0x") | Some("0X") => (16, &literal[2..]),
+            Some("0o") | Some("0O") => (8, &literal[2..]),
+            Some("0b") | Some("0B") => (2, &literal[2..]),
+            _ => (10, literal),
+        };
+        let digits: String = digits.chars().filter(|&c| c != '_').collect();
+
+        let value = if digits.chars().all(|c| c.is_digit(radix)) {
+            BigInt::parse_bytes(digits.as_bytes(), radix)
+        } else {
+            None
+        };
+
+        match value {
+            Some(value) => Token::Int(value),
+            None => Token::Illegal(LexError::MalformedNumber(String::from(literal))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::lexer::{LexError, Lexer};
+    use crate::token::token::Token;
+    use num_bigint::BigInt;
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let x = 5;\n  x == 10;";
+        let mut lexer = Lexer::new(input);
+
+        let mut tokens = vec![];
         loop {
-            match self.ch {
-                b'0'..=b'9' => {
-                    self.read_char();
-                }
-                _ => {
-                    break;
-                }
+            let tok = lexer.next_token();
+            if tok.token == Token::Eof {
+                break;
             }
+            tokens.push(tok);
         }
 
-        let literal = &self.input[position..self.position];
-        Token::Int(literal.parse::<i64>().unwrap())
+        let eq = &tokens[6];
+        assert_eq!(eq.token, Token::Equal);
+        assert_eq!(&input[eq.span.start..eq.span.end], "==");
+        assert_eq!((eq.span.line, eq.span.column), (2, 5));
+
+        let ten = &tokens[7];
+        assert_eq!(&input[ten.span.start..ten.span.end], "10");
+        assert_eq!((ten.span.line, ten.span.column), (2, 8));
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = r#"/// doc
+        let x = 4 / 2; // line
+        /* outer /* inner */ still outer */ x
+        /* unterminated"#;
+        let mut lexer = Lexer::new(input);
+
+        let expected = vec![
+            Token::DocComment(String::from(" doc")),
+            Token::Let,
+            Token::Ident(String::from("x")),
+            Token::Assign,
+            Token::Int(4.into()),
+            Token::Slash,
+            Token::Int(2.into()),
+            Token::Semicolon,
+            Token::Comment(String::from("// line")),
+            Token::Comment(String::from("/* outer /* inner */ still outer */")),
+            Token::Ident(String::from("x")),
+            Token::Illegal(LexError::UnterminatedComment),
+            Token::Eof,
+        ];
+
+        for token in expected {
+            assert_eq!(lexer.next_token().token, token);
+        }
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let input = "0 1_000 0xff 0b1010 0o17 21888242871839275222246405745257275088548364400416034343698204186575808495617 0x 12ab 0b102";
+        let mut lexer = Lexer::new(input);
+
+        let expected = vec![
+            Token::Int(0.into()),
+            Token::Int(1000.into()),
+            Token::Int(255.into()),
+            Token::Int(10.into()),
+            Token::Int(15.into()),
+            Token::Int(
+                BigInt::parse_bytes(
+                    b"21888242871839275222246405745257275088548364400416034343698204186575808495617",
+                    10,
+                )
+                .unwrap(),
+            ),
+            Token::Illegal(LexError::MalformedNumber(String::from("0x"))),
+            Token::Illegal(LexError::MalformedNumber(String::from("12ab"))),
+            Token::Illegal(LexError::MalformedNumber(String::from("0b102"))),
+            Token::Eof,
+        ];
+
+        for token in expected {
+            assert_eq!(lexer.next_token().token, token);
+        }
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let input = r#""x must be small" "say \"hi\"\n" "\q" "open"#;
+        let mut lexer = Lexer::new(input);
+
+        let expected = vec![
+            Token::Str(String::from("x must be small")),
+            Token::Str(String::from("say \"hi\"\n")),
+            Token::Illegal(LexError::InvalidEscape('q')),
+            Token::Illegal(LexError::UnterminatedString),
+            Token::Eof,
+        ];
+
+        for token in expected {
+            assert_eq!(lexer.next_token().token, token);
+        }
     }
 }
@@ -3,5 +3,8 @@ pub mod lexer;
 pub mod parser;
 pub mod token;
 
+pub mod compiler;
+pub mod field;
 pub mod ir;
+pub mod loader;
 pub mod vm;
@@ -0,0 +1,330 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    ast::ast::{self, Module, Statement},
+    lexer::lexer::Lexer,
+    parser::parser::{ParseError, Parser},
+    token::token::Span,
+};
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}", describe_parse_errors(path, errors))]
+    Parse {
+        path: PathBuf,
+        errors: Vec<ParseError>,
+    },
+    /// `cycle` starts and ends with the same file.
+    #[error("{}:{span}: import cycle: {}", path.display(), describe_cycle(cycle))]
+    ImportCycle {
+        path: PathBuf,
+        span: Span,
+        cycle: Vec<PathBuf>,
+    },
+    #[error("{}:{span}: `{}` is not a valid module name", path.display(), import.display())]
+    InvalidModuleName {
+        path: PathBuf,
+        span: Span,
+        import: PathBuf,
+    },
+    #[error("{}:{span}: module `{name}` is already imported", path.display())]
+    DuplicateModule {
+        path: PathBuf,
+        span: Span,
+        name: String,
+    },
+    #[error(
+        "{}:{span}: a module can only declare functions, structs, constants and imports",
+        path.display()
+    )]
+    InvalidModuleItem { path: PathBuf, span: Span },
+}
+
+fn describe_parse_errors(path: &Path, errors: &[ParseError]) -> String {
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|error| format!("{}:{}", path.display(), error))
+        .collect();
+    errors.join("\n")
+}
+
+fn describe_cycle(cycle: &[PathBuf]) -> String {
+    let files: Vec<String> = cycle
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    files.join(" -> ")
+}
+
+/// A file read by the loader. Spans into it start at `offset`.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub offset: usize,
+    pub source: String,
+}
+
+/// A program together with every file it was loaded from.
+#[derive(Debug)]
+pub struct Loaded {
+    pub program: ast::Program,
+    pub files: Vec<SourceFile>,
+}
+
+impl Loaded {
+    /// The file that `span` points into, for reporting compile errors.
+    pub fn file(&self, span: Span) -> Option<&SourceFile> {
+        self.files
+            .iter()
+            .find(|file| (file.offset..=file.offset + file.source.len()).contains(&span.start))
+    }
+}
+
+/// Reads and parses the program at `path`, replacing each `import "file";`
+/// with the parsed `file` as a `Statement::Module`. Import paths are relative
+/// to the importing file. A file imported twice is loaded twice, and each copy
+/// gets its own byte offsets, so spans stay unique across the whole program.
+pub fn load(path: impl AsRef<Path>) -> Result<Loaded, LoadError> {
+    let mut loader = Loader::default();
+    let program = loader.load(path.as_ref())?;
+
+    Ok(Loaded {
+        program,
+        files: loader.files,
+    })
+}
+
+#[derive(Default)]
+struct Loader {
+    files: Vec<SourceFile>,
+    next_offset: usize,
+    /// Files being loaded, importers first, as canonical paths
+    stack: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<ast::Program, LoadError> {
+        let io_error = |source| LoadError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let source = fs::read_to_string(path).map_err(io_error)?;
+        let canonical = path.canonicalize().map_err(io_error)?;
+
+        let offset = self.next_offset;
+        // One past the end, which is where the `Eof` token's span starts.
+        self.next_offset += source.len() + 1;
+        let mut program = Parser::new(Lexer::new(&source).with_offset(offset))
+            .parse_program()
+            .map_err(|errors| LoadError::Parse {
+                path: path.to_path_buf(),
+                errors,
+            })?;
+        self.files.push(SourceFile {
+            path: path.to_path_buf(),
+            offset,
+            source,
+        });
+
+        self.stack.push(canonical);
+        let result = self.imports(path, &mut program);
+        self.stack.pop();
+        result?;
+
+        Ok(program)
+    }
+
+    /// Loads the imports of `program`, which was read from `path`.
+    fn imports(&mut self, path: &Path, program: &mut ast::Program) -> Result<(), LoadError> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut names: Vec<String> = vec![];
+
+        for statement in program.iter_mut() {
+            let (import, span) = match statement {
+                Statement::Import { path, span } => (dir.join(path.as_str()), *span),
+                _ => continue,
+            };
+
+            let name = match import.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) if is_identifier(name) => name.to_string(),
+                _ => {
+                    return Err(LoadError::InvalidModuleName {
+                        path: path.to_path_buf(),
+                        span,
+                        import,
+                    })
+                }
+            };
+            if names.contains(&name) {
+                return Err(LoadError::DuplicateModule {
+                    path: path.to_path_buf(),
+                    span,
+                    name,
+                });
+            }
+
+            let canonical = import.canonicalize().map_err(|source| LoadError::Io {
+                path: import.clone(),
+                source,
+            })?;
+            if let Some(start) = self.stack.iter().position(|file| file == &canonical) {
+                let mut cycle = self.stack[start..].to_vec();
+                cycle.push(canonical);
+                return Err(LoadError::ImportCycle {
+                    path: path.to_path_buf(),
+                    span,
+                    cycle,
+                });
+            }
+
+            let module = self.load(&import)?;
+            for item in &module {
+                if !matches!(
+                    item,
+                    Statement::Function(_)
+                        | Statement::Struct(_)
+                        | Statement::Const { .. }
+                        | Statement::Module(_)
+                ) {
+                    return Err(LoadError::InvalidModuleItem {
+                        path: import,
+                        span: item.span(),
+                    });
+                }
+            }
+
+            names.push(name.clone());
+            *statement = Statement::Module(Module {
+                name,
+                path: import,
+                program: module,
+                span,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{load, LoadError};
+    use crate::compiler::{compile, resolve::resolve};
+    use crate::vm::{Value, VM};
+
+    /// Writes `files` into a fresh directory and returns its path.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zkdsl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, source) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = write_files(
+            "imports",
+            &[
+                (
+                    "lib/hash.zk",
+                    "import \"util.zk\";
+                    const ROUNDS = 2;
+                    struct State { a: field, b: field }
+                    function mix(s: State) {
+                        return State { a: s.a + s.b, b: util::double(s.a) };
+                    }
+                    function permute(s: State) {
+                        let mut t = s;
+                        for i in 0..ROUNDS { t = mix(t); }
+                        return t;
+                    }",
+                ),
+                ("lib/util.zk", "function double(x) { return x * 2; }"),
+                (
+                    "main.zk",
+                    "import \"lib/hash.zk\";
+                    function mix(x) { return x + 100; }
+                    private x: field;
+                    let s = hash::permute(hash::State { a: x, b: 1 });
+                    let rounds: [field; hash::ROUNDS] = [0, 0];
+                    return (mix(s.a), s.b);",
+                ),
+            ],
+        );
+
+        let mut loaded = load(dir.join("main.zk")).unwrap();
+        assert_eq!(loaded.files.len(), 3);
+        resolve(&mut loaded.program).unwrap();
+        let program = compile(&loaded.program).unwrap();
+        assert!(program.functions.contains_key("hash::util::double"));
+        assert!(program.functions.contains_key("hash::mix"));
+        assert!(program.functions.contains_key("mix"));
+
+        let mut vm = VM::new(program).with_inputs(vec![], vec![3.into()]);
+        vm.execute().unwrap();
+        let num = |value: i64| Value::Num {
+            value: value.into(),
+        };
+        // (3, 1) -> (4, 6) -> (10, 8)
+        assert_eq!(
+            vm.data,
+            vec![Value::Tuple {
+                elements: vec![num(110), num(8)]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = write_files(
+            "import-errors",
+            &[
+                ("a.zk", "import \"b.zk\";"),
+                ("b.zk", "import \"a.zk\";\nfunction f() { return 1; }"),
+                ("main.zk", "import \"a.zk\";"),
+                ("script.zk", "import \"statements.zk\";"),
+                ("statements.zk", "let x = 1;"),
+                ("missing.zk", "import \"nowhere.zk\";"),
+            ],
+        );
+
+        let error = load(dir.join("main.zk")).unwrap_err();
+        let LoadError::ImportCycle { cycle, span, .. } = &error else {
+            panic!("expected an import cycle, found {}", error);
+        };
+        let names: Vec<_> = cycle
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["a.zk", "b.zk", "a.zk"]);
+        assert_eq!((span.line, span.column), (1, 1));
+        assert!(error.to_string().contains("b.zk:1:1: import cycle: "));
+
+        assert!(matches!(
+            load(dir.join("script.zk")),
+            Err(LoadError::InvalidModuleItem { .. })
+        ));
+        assert!(matches!(
+            load(dir.join("missing.zk")),
+            Err(LoadError::Io { .. })
+        ));
+    }
+}
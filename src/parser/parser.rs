@@ -1,57 +1,112 @@
 use std::fmt;
 
 use crate::ast::ast::{
-    BinaryOperator, Block, Expression, Identifier, Literal, Precedence, Program, Statement,
-    UnaryOperator,
+    BinaryOperator, Block, Expression, Function, Identifier, Literal, Parameter, Pattern,
+    Precedence, Program, Statement, Struct, StructField, Type, UnaryOperator, Visibility,
 };
-use crate::lexer::lexer::Lexer;
-use crate::token::token::Token;
+use crate::lexer::lexer::{LexError, Lexer};
+use crate::token::token::{Span, Token};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ParseErrorKind {
     UnexpectedToken,
+    IllegalToken(LexError),
+    MissingSemicolon,
+    /// A `{` without its `}`. The error's span is the opening brace.
+    UnclosedBlock,
+    InvalidAssignmentTarget,
+    InvalidCallTarget,
+    ExpectedExpression,
+    ExpectedIdentifier,
+    ExpectedType,
+    ExpectedString,
+    NestingTooDeep,
 }
 
 impl fmt::Display for ParseErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            ParseErrorKind::UnexpectedToken => write!(f, "Unexpected Token"),
+        match self {
+            ParseErrorKind::UnexpectedToken => write!(f, "unexpected token"),
+            ParseErrorKind::IllegalToken(error) => write!(f, "{}", error),
+            ParseErrorKind::MissingSemicolon => write!(f, "missing semicolon"),
+            ParseErrorKind::UnclosedBlock => write!(f, "unclosed block"),
+            ParseErrorKind::InvalidAssignmentTarget => write!(f, "invalid assignment target"),
+            ParseErrorKind::InvalidCallTarget => write!(f, "only named functions can be called"),
+            ParseErrorKind::ExpectedExpression => write!(f, "expected expression"),
+            ParseErrorKind::ExpectedIdentifier => write!(f, "expected identifier"),
+            ParseErrorKind::ExpectedType => write!(f, "expected type"),
+            ParseErrorKind::ExpectedString => write!(f, "expected string literal"),
+            ParseErrorKind::NestingTooDeep => {
+                write!(f, "nesting deeper than {} levels", MAX_NESTING_DEPTH)
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParseError {
-    kind: ParseErrorKind,
-    msg: String,
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    /// The token the parser stopped at
+    pub found: Token,
+    /// Tokens that would have been accepted instead of `found`, if the parser
+    /// was looking for specific ones
+    pub expected: Vec<Token>,
 }
 
-impl ParseError {
-    fn new(kind: ParseErrorKind, msg: String) -> Self {
-        ParseError { kind, msg }
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Eof => token.to_string(),
+        _ => format!("`{}`", token),
     }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.kind, self.msg)
+        write!(f, "{}: {}", self.span, self.kind)?;
+        if let Some((last, rest)) = self.expected.split_last() {
+            let rest: Vec<String> = rest.iter().map(describe).collect();
+            write!(f, ": expected ")?;
+            match rest.len() {
+                0 => {}
+                1 => write!(f, "{} or ", rest[0])?,
+                _ => write!(f, "{}, or ", rest.join(", "))?,
+            }
+            write!(f, "{}", describe(last))?;
+        }
+        write!(f, ", found {}", describe(&self.found))
     }
 }
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     cur_token: Token,
+    cur_span: Span,
     peek_token: Token,
+    peek_span: Span,
     errors: Vec<ParseError>,
+    /// Current nesting of blocks and expressions, bounded so deep input
+    /// cannot overflow the stack.
+    depth: usize,
+    /// Whether `Name {` starts a struct literal. Off in `if` conditions and
+    /// loop bounds, where the `{` opens the body instead.
+    struct_literals: bool,
 }
 
+/// Deepest nesting of blocks and expressions the parser accepts.
+pub const MAX_NESTING_DEPTH: usize = 256;
+
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
         let mut parser = Parser {
             lexer,
             cur_token: Token::Eof,
+            cur_span: Span::default(),
             peek_token: Token::Eof,
+            peek_span: Span::default(),
             errors: Vec::new(),
+            depth: 0,
+            struct_literals: true,
         };
 
         parser.next_token();
@@ -62,23 +117,52 @@ impl<'a> Parser<'a> {
 
     fn token_to_precedence(token: &Token) -> Precedence {
         match token {
+            Token::Or => Precedence::LogicalOr,
+            Token::And => Precedence::LogicalAnd,
             Token::Equal | Token::NotEqual => Precedence::Equals,
-            Token::LessThan => Precedence::LessGreater,
-            Token::GreaterThan => Precedence::LessGreater,
+            Token::LessThan | Token::LessEqual => Precedence::LessGreater,
+            Token::GreaterThan | Token::GreaterEqual => Precedence::LessGreater,
+            Token::Pipe => Precedence::BitOr,
+            Token::Caret => Precedence::BitXor,
+            Token::Ampersand => Precedence::BitAnd,
+            Token::ShiftLeft | Token::ShiftRight => Precedence::Shift,
             Token::Plus | Token::Minus => Precedence::Sum,
-            Token::Asterisk | Token::Slash => Precedence::Product,
+            Token::Asterisk | Token::Slash | Token::Percent => Precedence::Product,
+            Token::Lparen => Precedence::Call,
+            Token::Lbracket | Token::Dot => Precedence::Index,
             _ => Precedence::Lowest,
         }
     }
 
-    #[allow(dead_code)]
-    fn get_errors(&mut self) -> Vec<ParseError> {
-        self.errors.clone()
+    pub fn get_errors(&self) -> &[ParseError] {
+        &self.errors
     }
 
     fn next_token(&mut self) {
-        self.cur_token = self.peek_token.clone();
-        self.peek_token = self.lexer.next_token();
+        let mut next = self.lexer.next_token();
+        while next.token.is_trivia() {
+            next = self.lexer.next_token();
+        }
+        if let Token::Illegal(error) = &next.token {
+            self.errors.push(ParseError {
+                kind: ParseErrorKind::IllegalToken(error.clone()),
+                span: next.span,
+                found: next.token.clone(),
+                expected: vec![],
+            });
+        }
+        self.cur_token = std::mem::replace(&mut self.peek_token, next.token);
+        self.cur_span = std::mem::replace(&mut self.peek_span, next.span);
+    }
+
+    /// Enters one level of nesting, reporting an error past `MAX_NESTING_DEPTH`.
+    fn enter(&mut self) -> bool {
+        if self.depth >= MAX_NESTING_DEPTH {
+            self.error_cur(ParseErrorKind::NestingTooDeep, vec![]);
+            return false;
+        }
+        self.depth += 1;
+        true
     }
 
     fn cur_token_is(&mut self, token: Token) -> bool {
@@ -99,6 +183,34 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn expect_peek_identifier(&mut self) -> Option<Identifier> {
+        if let Token::Ident(_) = self.peek_token {
+            self.next_token();
+            self.parse_identifier()
+        } else {
+            self.error_peek(ParseErrorKind::ExpectedIdentifier, vec![]);
+            None
+        }
+    }
+
+    fn expect_peek_string(&mut self) -> Option<String> {
+        if let Token::Str(value) = &self.peek_token {
+            let value = value.clone();
+            self.next_token();
+            Some(value)
+        } else {
+            self.error_peek(ParseErrorKind::ExpectedString, vec![]);
+            None
+        }
+    }
+
+    fn with_struct_literals<T>(&mut self, allowed: bool, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let saved = std::mem::replace(&mut self.struct_literals, allowed);
+        let result = parse(self);
+        self.struct_literals = saved;
+        result
+    }
+
     fn cur_token_precedence(&mut self) -> Precedence {
         Self::token_to_precedence(&self.cur_token)
     }
@@ -108,28 +220,73 @@ impl<'a> Parser<'a> {
     }
 
     fn error_next_token(&mut self, token: Token) {
-        self.errors.push(ParseError::new(
-            ParseErrorKind::UnexpectedToken,
-            format!(
-                "expected next token to be {:?}, got {:?} instead",
-                token, self.peek_token
-            ),
-        ));
+        let kind = match token {
+            Token::Semicolon => ParseErrorKind::MissingSemicolon,
+            _ => ParseErrorKind::UnexpectedToken,
+        };
+        self.error_peek(kind, vec![token]);
+    }
+
+    fn error_peek(&mut self, kind: ParseErrorKind, expected: Vec<Token>) {
+        self.errors.push(ParseError {
+            kind,
+            span: self.peek_span,
+            found: self.peek_token.clone(),
+            expected,
+        });
     }
 
-    pub fn parse_program(&mut self) -> Program {
+    fn error_cur(&mut self, kind: ParseErrorKind, expected: Vec<Token>) {
+        self.errors.push(ParseError {
+            kind,
+            span: self.cur_span,
+            found: self.cur_token.clone(),
+            expected,
+        });
+    }
+
+    /// Parses the whole input. On failure every error found is returned, not just the first.
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut program = Vec::new();
 
         while self.cur_token != Token::Eof {
             match self.parse_statement() {
                 Some(statement) => program.push(statement),
-                None => {}
+                None => self.synchronize(),
             }
 
             self.next_token();
         }
 
-        program
+        if self.errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+
+    /// Skips the rest of a statement that failed to parse. Stops on its `;`, or
+    /// before anything that starts a new statement or closes the enclosing block.
+    fn synchronize(&mut self) {
+        while !self.cur_token_is(Token::Semicolon) && !self.cur_token_is(Token::Eof) {
+            match self.peek_token {
+                Token::Let
+                | Token::Return
+                | Token::If
+                | Token::Assert
+                | Token::Commit
+                | Token::Function
+                | Token::For
+                | Token::Struct
+                | Token::Const
+                | Token::Import
+                | Token::Public
+                | Token::Private
+                | Token::Rbrace
+                | Token::Eof => return,
+                _ => self.next_token(),
+            }
+        }
     }
 
     fn parse_statement(&mut self) -> Option<Statement> {
@@ -138,19 +295,35 @@ impl<'a> Parser<'a> {
             Token::Return => self.parse_return_statement(),
             Token::If => self.parse_if_statement(),
             Token::Assert => self.parse_assert_statement(),
+            Token::Commit => self.parse_commit_statement(),
+            Token::Function => self.parse_function_statement(),
+            Token::For => self.parse_for_statement(),
+            Token::Struct => self.parse_struct_statement(),
+            Token::Const => self.parse_const_statement(),
+            Token::Import => self.parse_import_statement(),
+            Token::Public | Token::Private => self.parse_input_statement(),
+            Token::Ident(_) if self.peek_token.is_assignment() => self.parse_assign_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
     fn parse_let_statement(&mut self) -> Option<Statement> {
-        match &self.peek_token {
-            Token::Ident(_) => self.next_token(),
-            _ => return None,
-        };
+        let start = self.cur_span;
+
+        let mutable = self.peek_token_is(Token::Mut);
+        if mutable {
+            self.next_token();
+        }
+
+        self.next_token();
+        let pattern = self.parse_pattern()?;
 
-        let identifier = match self.parse_identifier() {
-            Some(identifier) => identifier,
-            None => return None,
+        let ty = if self.peek_token_is(Token::Colon) {
+            self.next_token();
+            self.next_token();
+            Some(self.parse_type()?)
+        } else {
+            None
         };
 
         if !self.expect_peek(Token::Assign) {
@@ -159,247 +332,1113 @@ impl<'a> Parser<'a> {
 
         self.next_token();
 
-        let expression = match self.parse_expression(Precedence::Lowest) {
-            Some(expression) => expression,
-            None => return None,
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
+        }
+
+        Some(Statement::Let {
+            pattern,
+            mutable,
+            ty,
+            value,
+            span: start.to(self.cur_span),
+        })
+    }
+
+    /// `x = e;` or `x op= e;`, starting at `x`.
+    fn parse_assign_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
+        let target = self.parse_identifier()?;
+
+        self.next_token();
+        let op = match self.cur_token {
+            Token::Assign => None,
+            Token::PlusAssign => Some(BinaryOperator::Plus),
+            Token::MinusAssign => Some(BinaryOperator::Minus),
+            Token::AsteriskAssign => Some(BinaryOperator::Multiply),
+            _ => return None,
         };
 
-        while !self.cur_token_is(Token::Semicolon) {
-            self.next_token();
+        self.next_token();
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
 
-        Some(Statement::Let(identifier, expression))
+        Some(Statement::Assign {
+            target,
+            op,
+            value,
+            span: start.to(self.cur_span),
+        })
     }
 
     fn parse_return_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
         self.next_token();
 
-        let expression = match self.parse_expression(Precedence::Lowest) {
-            Some(expression) => expression,
-            None => return None,
-        };
+        let expression = self.parse_expression(Precedence::Lowest)?;
 
-        while !self.cur_token_is(Token::Semicolon) {
-            self.next_token();
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
 
-        Some(Statement::Return(expression))
+        Some(Statement::Return(expression, start.to(self.cur_span)))
     }
 
     fn parse_if_statement(&mut self) -> Option<Statement> {
-        self.next_token();
-        let expression = match self.parse_expression(Precedence::Lowest) {
-            Some(expression) => expression,
-            None => return None,
+        let (condition, if_block, else_block, span) = self.parse_if(false)?;
+
+        Some(Statement::Ifelse(
+            condition,
+            if_block,
+            else_block.unwrap_or(Block(Vec::new())),
+            span,
+        ))
+    }
+
+    fn parse_if_expression(&mut self) -> Option<Expression> {
+        let (condition, if_block, else_block, span) = self.parse_if(true)?;
+
+        let else_block = match else_block {
+            Some(else_block) => else_block,
+            None => {
+                self.error_peek(ParseErrorKind::UnexpectedToken, vec![Token::Else]);
+                return None;
+            }
         };
 
-        if !self.expect_peek(Token::Lbrace) {
-            return None;
+        Some(Expression::If(
+            Box::new(condition),
+            Self::trailing_if_as_value(if_block),
+            Self::trailing_if_as_value(else_block),
+            span,
+        ))
+    }
+
+    /// A branch of an if-expression that ends in an `if` with an `else` takes
+    /// that `if`'s value, so it is turned into its expression form.
+    fn trailing_if_as_value(mut block: Block) -> Block {
+        match block.0.pop() {
+            Some(Statement::Ifelse(condition, if_block, else_block, span))
+                if !else_block.0.is_empty() =>
+            {
+                let value = Expression::If(
+                    Box::new(condition),
+                    Self::trailing_if_as_value(if_block),
+                    Self::trailing_if_as_value(else_block),
+                    span,
+                );
+                block.0.push(Statement::Expression(value, span));
+            }
+            Some(statement) => block.0.push(statement),
+            None => {}
         }
+        block
+    }
 
-        let mut if_block = Vec::new();
+    /// Parses `if cond { .. }` with an optional `else { .. }` or `else if ..` chain,
+    /// starting at the `if`. In an `else if` chain the nested `if` becomes the only
+    /// statement of the else block, as an expression when `as_expression` is set.
+    fn parse_if(
+        &mut self,
+        as_expression: bool,
+    ) -> Option<(Expression, Block, Option<Block>, Span)> {
+        let start = self.cur_span;
         self.next_token();
-        while self.cur_token != Token::Rbrace {
-            match self.parse_statement() {
-                Some(statement) => if_block.push(statement),
-                None => {}
-            }
-            self.next_token();
+        let condition =
+            self.with_struct_literals(false, |p| p.parse_expression(Precedence::Lowest))?;
+
+        if !self.expect_peek(Token::Lbrace) {
+            return None;
         }
+        let if_block = self.parse_block()?;
 
         if !self.peek_token_is(Token::Else) {
-            return Some(Statement::Ifelse(
-                expression,
-                Block(if_block),
-                Block(Vec::new()),
-            ));
+            return Some((condition, if_block, None, start.to(self.cur_span)));
         }
         self.next_token();
 
-        if !self.expect_peek(Token::Lbrace) {
+        let else_block = if self.peek_token_is(Token::If) {
+            self.next_token();
+            let nested = if as_expression {
+                let expression = self.parse_if_expression()?;
+                let span = expression.span();
+                Statement::Expression(expression, span)
+            } else {
+                self.parse_if_statement()?
+            };
+            Block(vec![nested])
+        } else {
+            if !self.expect_peek(Token::Lbrace) {
+                return None;
+            }
+            self.parse_block()?
+        };
+
+        Some((
+            condition,
+            if_block,
+            Some(else_block),
+            start.to(self.cur_span),
+        ))
+    }
+
+    fn parse_for_statement(&mut self) -> Option<Statement> {
+        let start_span = self.cur_span;
+
+        let var = self.expect_peek_identifier()?;
+        if !self.expect_peek(Token::In) {
             return None;
         }
 
-        let mut else_block = Vec::new();
-        while self.cur_token != Token::Rbrace {
-            match self.parse_statement() {
-                Some(statement) => else_block.push(statement),
-                None => {}
-            }
-            self.next_token();
+        self.next_token();
+        let start = self.with_struct_literals(false, |p| p.parse_expression(Precedence::Lowest))?;
+        if !self.expect_peek(Token::DotDot) {
+            return None;
         }
 
-        Some(Statement::Ifelse(
-            expression,
-            Block(if_block),
-            Block(else_block),
-        ))
+        self.next_token();
+        let end = self.with_struct_literals(false, |p| p.parse_expression(Precedence::Lowest))?;
+        if !self.expect_peek(Token::Lbrace) {
+            return None;
+        }
+        let body = self.parse_block()?;
+
+        Some(Statement::For {
+            var,
+            start,
+            end,
+            body,
+            span: start_span.to(self.cur_span),
+        })
     }
 
     fn parse_assert_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
         self.next_token();
 
-        let expression = match self.parse_expression(Precedence::Lowest) {
-            Some(expression) => expression,
-            None => return None,
+        let (condition, message) = if self.cur_token_is(Token::Lparen) {
+            self.parse_assert_arguments()?
+        } else {
+            (self.parse_expression(Precedence::Lowest)?, None)
         };
 
-        while !self.cur_token_is(Token::Semicolon) {
-            self.next_token();
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
 
-        Some(Statement::Assert(expression))
+        Some(Statement::Assert(
+            condition,
+            message,
+            start.to(self.cur_span),
+        ))
     }
 
-    fn parse_expression_statement(&mut self) -> Option<Statement> {
-        match self.parse_expression(Precedence::Lowest) {
-            Some(expression) => {
-                if self.peek_token_is(Token::Semicolon) {
-                    self.next_token();
+    /// `(condition, "message")`, starting at `(`. It is only that form if a
+    /// string follows the comma; otherwise the parentheses group or make a
+    /// tuple, so `assert (a) == b;` and `assert (a, b) == c;` still work.
+    fn parse_assert_arguments(&mut self) -> Option<(Expression, Option<String>)> {
+        if self.peek_token_is(Token::Rparen) {
+            return Some((self.parse_expression(Precedence::Lowest)?, None));
+        }
+
+        let start = self.cur_span;
+        self.next_token();
+        let first = self.with_struct_literals(true, |p| p.parse_expression(Precedence::Lowest))?;
+
+        let group = if self.peek_token_is(Token::Comma) {
+            self.next_token();
+            if let Token::Str(_) = self.peek_token {
+                let message = self.expect_peek_string()?;
+                if !self.expect_peek(Token::Rparen) {
+                    return None;
                 }
-                Some(Statement::Expression(expression))
+                return Some((first, Some(message)));
             }
-            None => None,
+            let mut elements = vec![first];
+            elements.append(&mut self.parse_expression_list(Token::Rparen)?);
+            Expression::Tuple(elements, start.to(self.cur_span))
+        } else {
+            if !self.expect_peek(Token::Rparen) {
+                return None;
+            }
+            first
+        };
+        let condition = self.parse_infix_expressions(group, Precedence::Lowest)?;
+        Some((condition, None))
+    }
+
+    fn parse_commit_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
+        self.next_token();
+
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
+
+        Some(Statement::Commit(expression, start.to(self.cur_span)))
     }
 
-    // RBP: Right Binding Power
-    fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
-        // Prefix
-        let mut left = match self.cur_token {
-            Token::Ident(_) => self.parse_identifier_expression(),
-            Token::Int(_) => self.parse_int_expression(),
-            Token::Bool(_) => self.parse_bool_expression(),
-            Token::Lparen => self.parse_grouped_expression(),
-            Token::Bang | Token::Minus => self.parse_prefix_expression(),
-            _ => None,
-        };
+    fn parse_function_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
 
-        while !self.peek_token_is(Token::Semicolon) && precedence < self.peek_token_precedence() {
-            match self.peek_token {
-                Token::Plus
-                | Token::Minus
-                | Token::Asterisk
-                | Token::Slash
-                | Token::Equal
-                | Token::NotEqual
-                | Token::LessThan
-                | Token::GreaterThan => {
+        let name = self.expect_peek_identifier()?;
+
+        if !self.expect_peek(Token::Lparen) {
+            return None;
+        }
+        let mut params = Vec::new();
+        if self.peek_token_is(Token::Rparen) {
+            self.next_token();
+        } else {
+            loop {
+                params.push(self.parse_parameter()?);
+
+                if self.peek_token_is(Token::Comma) {
+                    self.next_token();
+                } else if self.peek_token_is(Token::Rparen) {
                     self.next_token();
-                    left = self.parse_infix_expression(left.unwrap());
+                    break;
+                } else {
+                    self.error_peek(
+                        ParseErrorKind::UnexpectedToken,
+                        vec![Token::Comma, Token::Rparen],
+                    );
+                    return None;
                 }
-                _ => return left,
             }
         }
 
-        left
+        if !self.expect_peek(Token::Lbrace) {
+            return None;
+        }
+        let body = self.parse_block()?;
+
+        Some(Statement::Function(Function {
+            name,
+            params,
+            body,
+            span: start.to(self.cur_span),
+        }))
     }
 
-    fn parse_identifier(&mut self) -> Option<Identifier> {
-        match &self.cur_token {
-            Token::Ident(ident) => Some(Identifier(ident.clone())),
-            _ => None,
-        }
+    /// Parses `{ statements }` starting at the `{`, leaving the `}` as the current token.
+    fn parse_block(&mut self) -> Option<Block> {
+        self.with_struct_literals(true, Self::parse_block_inner)
     }
 
-    fn parse_identifier_expression(&mut self) -> Option<Expression> {
-        match self.parse_identifier() {
-            Some(ident) => Some(Expression::Identifier(ident)),
-            None => None,
+    fn parse_block_inner(&mut self) -> Option<Block> {
+        if !self.enter() {
+            return None;
         }
-    }
 
-    fn parse_int_expression(&mut self) -> Option<Expression> {
-        match &self.cur_token {
-            Token::Int(int) => Some(Expression::Literal(Literal::Int(*int))),
-            _ => None,
+        let open = self.cur_span;
+        let mut statements = Vec::new();
+        self.next_token();
+
+        while !self.cur_token_is(Token::Rbrace) {
+            if self.cur_token_is(Token::Eof) {
+                self.errors.push(ParseError {
+                    kind: ParseErrorKind::UnclosedBlock,
+                    span: open,
+                    found: Token::Eof,
+                    expected: vec![Token::Rbrace],
+                });
+                self.depth -= 1;
+                return None;
+            }
+            match self.parse_statement() {
+                Some(statement) => statements.push(statement),
+                None => self.synchronize(),
+            }
+            self.next_token();
         }
+
+        self.depth -= 1;
+        Some(Block(statements))
     }
 
-    fn parse_bool_expression(&mut self) -> Option<Expression> {
-        match self.cur_token {
-            Token::Bool(value) => Some(Expression::Literal(Literal::Bool(value))),
-            _ => None,
+    fn parse_expression_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
+
+        match self.parse_expression(Precedence::Lowest) {
+            Some(expression) => {
+                if self.peek_token.is_assignment() {
+                    self.errors.push(ParseError {
+                        kind: ParseErrorKind::InvalidAssignmentTarget,
+                        span: expression.span(),
+                        found: self.peek_token.clone(),
+                        expected: vec![],
+                    });
+                    return None;
+                }
+                // Only the value at the end of a block may leave out its `;`.
+                if self.peek_token_is(Token::Semicolon) {
+                    self.next_token();
+                } else if !self.peek_token_is(Token::Rbrace) {
+                    self.error_next_token(Token::Semicolon);
+                }
+                Some(Statement::Expression(expression, start.to(self.cur_span)))
+            }
+            None => None,
         }
     }
 
-    fn parse_grouped_expression(&mut self) -> Option<Expression> {
-        self.next_token();
-
-        let expr = self.parse_expression(Precedence::Lowest);
+    /// `struct Name { field: type, ... }`
+    fn parse_struct_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
+        let name = self.expect_peek_identifier()?;
+        if !self.expect_peek(Token::Lbrace) {
+            return None;
+        }
 
-        if !self.expect_peek(Token::Rparen) {
-            None
-        } else {
-            expr
+        let mut fields = Vec::new();
+        while !self.peek_token_is(Token::Rbrace) {
+            let field = self.expect_peek_identifier()?;
+            if !self.expect_peek(Token::Colon) {
+                return None;
+            }
+            self.next_token();
+            fields.push(StructField {
+                name: field,
+                ty: self.parse_type()?,
+            });
+
+            if self.peek_token_is(Token::Comma) {
+                self.next_token();
+            } else if !self.peek_token_is(Token::Rbrace) {
+                self.error_peek(
+                    ParseErrorKind::UnexpectedToken,
+                    vec![Token::Comma, Token::Rbrace],
+                );
+                return None;
+            }
         }
+        self.next_token();
+
+        Some(Statement::Struct(Struct {
+            name,
+            fields,
+            span: start.to(self.cur_span),
+        }))
     }
 
-    fn parse_prefix_expression(&mut self) -> Option<Expression> {
-        let operator = match self.cur_token {
-            Token::Bang => UnaryOperator::Not,
-            Token::Minus => UnaryOperator::Minus,
-            _ => return None,
+    /// `public name: type;` or `private name: type;`
+    fn parse_input_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
+        let visibility = match self.cur_token {
+            Token::Public => Visibility::Public,
+            _ => Visibility::Private,
         };
 
+        let name = self.expect_peek_identifier()?;
+        if !self.expect_peek(Token::Colon) {
+            return None;
+        }
         self.next_token();
-
-        match self.parse_expression(Precedence::Prefix) {
-            Some(expr) => Some(Expression::UnaryOperation(operator, Box::new(expr))),
-            None => None,
+        let ty = self.parse_type()?;
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
-    }
 
-    fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
-        let operator = match self.cur_token {
-            Token::Plus => BinaryOperator::Plus,
-            Token::Minus => BinaryOperator::Minus,
-            Token::Asterisk => BinaryOperator::Multiply,
-            Token::Slash => BinaryOperator::Divide,
-            Token::Equal => BinaryOperator::Equal,
-            Token::NotEqual => BinaryOperator::NotEqual,
-            Token::LessThan => BinaryOperator::LessThan,
-            Token::GreaterThan => BinaryOperator::GreaterThan,
-            _ => return None,
-        };
+        Some(Statement::Input {
+            visibility,
+            name,
+            ty,
+            span: start.to(self.cur_span),
+        })
+    }
 
-        let precedence = self.cur_token_precedence();
+    fn parse_const_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
 
+        let name = self.expect_peek_identifier()?;
+        if !self.expect_peek(Token::Assign) {
+            return None;
+        }
         self.next_token();
-
-        match self.parse_expression(precedence) {
-            Some(expr) => Some(Expression::BinaryOperation(
-                operator,
-                Box::new(left),
-                Box::new(expr),
-            )),
-            None => None,
+        let value = self.parse_expression(Precedence::Lowest)?;
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::ast::ast::{BinaryOperator, Block, Expression, Identifier, Literal, Statement};
-    use crate::lexer::lexer::Lexer;
-    use crate::parser::parser::Parser;
+        Some(Statement::Const {
+            name,
+            value,
+            span: start.to(self.cur_span),
+        })
+    }
 
-    fn check_parse_errors(parser: &mut Parser) {
-        let errors = parser.get_errors();
+    fn parse_import_statement(&mut self) -> Option<Statement> {
+        let start = self.cur_span;
 
-        if errors.is_empty() {
-            return;
+        let path = self.expect_peek_string()?;
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
         }
 
-        println!("\n");
-        println!("parser has {} errors", errors.len());
+        Some(Statement::Import {
+            path,
+            span: start.to(self.cur_span),
+        })
+    }
 
-        for error in errors {
-            println!("parse error: {:?}", error);
+    /// `x` or `(a, (b, c))`, starting at its first token.
+    fn parse_pattern(&mut self) -> Option<Pattern> {
+        if !self.enter() {
+            return None;
         }
+        let pattern = self.parse_pattern_inner();
+        self.depth -= 1;
+        pattern
+    }
 
-        println!("\n");
-        panic!("failed");
+    fn parse_pattern_inner(&mut self) -> Option<Pattern> {
+        match self.cur_token {
+            Token::Ident(_) => self.parse_identifier().map(Pattern::Identifier),
+            Token::Lparen => {
+                let start = self.cur_span;
+                let mut patterns = Vec::new();
+                while !self.peek_token_is(Token::Rparen) {
+                    self.next_token();
+                    patterns.push(self.parse_pattern()?);
+
+                    if self.peek_token_is(Token::Comma) {
+                        self.next_token();
+                    } else if !self.peek_token_is(Token::Rparen) {
+                        self.error_peek(
+                            ParseErrorKind::UnexpectedToken,
+                            vec![Token::Comma, Token::Rparen],
+                        );
+                        return None;
+                    }
+                }
+                self.next_token();
+                Some(Pattern::Tuple(patterns, start.to(self.cur_span)))
+            }
+            _ => {
+                self.error_cur(ParseErrorKind::ExpectedIdentifier, vec![]);
+                None
+            }
+        }
+    }
+
+    /// Parses `name` or `name: type`, starting before the name.
+    fn parse_parameter(&mut self) -> Option<Parameter> {
+        let name = self.expect_peek_identifier()?;
+
+        let ty = if self.peek_token_is(Token::Colon) {
+            self.next_token();
+            self.next_token();
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        Some(Parameter { name, ty })
+    }
+
+    fn parse_type(&mut self) -> Option<Type> {
+        if !self.enter() {
+            return None;
+        }
+        let ty = self.parse_type_inner();
+        self.depth -= 1;
+        ty
+    }
+
+    fn parse_type_inner(&mut self) -> Option<Type> {
+        match &self.cur_token {
+            Token::Ident(name) if name == "field" => Some(Type::Field),
+            Token::Ident(name) if name == "bool" => Some(Type::Bool),
+            Token::Ident(name) if name == "u8" => Some(Type::U8),
+            Token::Ident(name) if name == "u32" => Some(Type::U32),
+            Token::Ident(name) if name == "u64" => Some(Type::U64),
+            Token::Ident(_) => self.parse_path().map(Type::Named),
+            Token::Lparen => {
+                let mut types = Vec::new();
+                if self.peek_token_is(Token::Rparen) {
+                    self.next_token();
+                    return Some(Type::Tuple(types));
+                }
+                loop {
+                    self.next_token();
+                    types.push(self.parse_type()?);
+                    if !self.peek_token_is(Token::Comma) {
+                        break;
+                    }
+                    self.next_token();
+                }
+                if !self.expect_peek(Token::Rparen) {
+                    return None;
+                }
+                Some(Type::Tuple(types))
+            }
+            Token::Lbracket => {
+                self.next_token();
+                let element = self.parse_type()?;
+
+                if !self.expect_peek(Token::Semicolon) {
+                    return None;
+                }
+                self.next_token();
+                let len = self.parse_expression(Precedence::Lowest)?;
+
+                if !self.expect_peek(Token::Rbracket) {
+                    return None;
+                }
+
+                Some(Type::Array(Box::new(element), Box::new(len)))
+            }
+            _ => {
+                self.error_cur(ParseErrorKind::ExpectedType, vec![]);
+                None
+            }
+        }
+    }
+
+    // RBP: Right Binding Power
+    fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if !self.enter() {
+            return None;
+        }
+        let expression = self.parse_expression_inner(precedence);
+        self.depth -= 1;
+        expression
+    }
+
+    fn parse_expression_inner(&mut self, precedence: Precedence) -> Option<Expression> {
+        // Prefix
+        let left = match self.cur_token {
+            Token::Ident(_) => self.parse_path_expression(),
+            Token::Int(_) => self.parse_int_expression(),
+            Token::Bool(_) => self.parse_bool_expression(),
+            Token::Lparen => self.parse_grouped_expression(),
+            Token::Bang | Token::Minus => self.parse_prefix_expression(),
+            Token::If => self.parse_if_expression(),
+            Token::Lbracket => self.parse_array_expression(),
+            // Already reported by `next_token`.
+            Token::Illegal(_) => None,
+            _ => {
+                self.error_cur(ParseErrorKind::ExpectedExpression, vec![]);
+                None
+            }
+        }?;
+
+        self.parse_infix_expressions(left, precedence)
+    }
+
+    /// Extends `left` with operators, calls, indexing and field accesses that
+    /// bind tighter than `precedence`.
+    fn parse_infix_expressions(
+        &mut self,
+        mut left: Expression,
+        precedence: Precedence,
+    ) -> Option<Expression> {
+        while !self.peek_token_is(Token::Semicolon) && precedence < self.peek_token_precedence() {
+            match self.peek_token {
+                Token::Plus
+                | Token::Minus
+                | Token::Asterisk
+                | Token::Slash
+                | Token::Percent
+                | Token::Equal
+                | Token::NotEqual
+                | Token::LessThan
+                | Token::GreaterThan
+                | Token::LessEqual
+                | Token::GreaterEqual
+                | Token::And
+                | Token::Or
+                | Token::Caret
+                | Token::Ampersand
+                | Token::Pipe
+                | Token::ShiftLeft
+                | Token::ShiftRight => {
+                    self.next_token();
+                    left = self.parse_infix_expression(left)?;
+                }
+                Token::Lparen => {
+                    self.next_token();
+                    left = self.parse_call_expression(left)?;
+                }
+                Token::Lbracket => {
+                    self.next_token();
+                    left = self.parse_index_expression(left)?;
+                }
+                Token::Dot => {
+                    self.next_token();
+                    left = self.parse_field_expression(left)?;
+                }
+                _ => return Some(left),
+            }
+        }
+
+        Some(left)
+    }
+
+    fn parse_identifier(&mut self) -> Option<Identifier> {
+        match &self.cur_token {
+            Token::Ident(ident) => Some(Identifier {
+                name: ident.clone(),
+                span: self.cur_span,
+                binding: None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// `name` or `module::name`, starting at the first segment.
+    fn parse_path(&mut self) -> Option<Identifier> {
+        let mut path = self.parse_identifier()?;
+        while self.peek_token_is(Token::ColonColon) {
+            self.next_token();
+            let segment = self.expect_peek_identifier()?;
+            path.name = format!("{}::{}", path.name, segment.name);
+            path.span = path.span.to(segment.span);
+        }
+        Some(path)
+    }
+
+    /// A variable, or a struct literal if a `{` follows the name.
+    fn parse_path_expression(&mut self) -> Option<Expression> {
+        let path = self.parse_path()?;
+        if self.struct_literals && self.peek_token_is(Token::Lbrace) {
+            return self.parse_struct_expression(path);
+        }
+        Some(Expression::Identifier(path))
+    }
+
+    fn parse_int_expression(&mut self) -> Option<Expression> {
+        match &self.cur_token {
+            Token::Int(int) => Some(Expression::Literal(
+                Literal::Int(int.clone()),
+                self.cur_span,
+            )),
+            _ => None,
+        }
+    }
+
+    fn parse_bool_expression(&mut self) -> Option<Expression> {
+        match self.cur_token {
+            Token::Bool(value) => Some(Expression::Literal(Literal::Bool(value), self.cur_span)),
+            _ => None,
+        }
+    }
+
+    /// `(e)`, or a tuple if there is a comma or nothing between the parentheses.
+    fn parse_grouped_expression(&mut self) -> Option<Expression> {
+        let start = self.cur_span;
+        if self.peek_token_is(Token::Rparen) {
+            self.next_token();
+            return Some(Expression::Tuple(vec![], start.to(self.cur_span)));
+        }
+
+        self.next_token();
+        let expr = self.with_struct_literals(true, |p| p.parse_expression(Precedence::Lowest))?;
+        if !self.peek_token_is(Token::Comma) {
+            return if self.expect_peek(Token::Rparen) {
+                Some(expr)
+            } else {
+                None
+            };
+        }
+
+        self.next_token();
+        let mut elements = vec![expr];
+        elements.append(&mut self.parse_expression_list(Token::Rparen)?);
+
+        Some(Expression::Tuple(elements, start.to(self.cur_span)))
+    }
+
+    /// `Name { field: value, ... }`, starting at the end of `Name`.
+    fn parse_struct_expression(&mut self, name: Identifier) -> Option<Expression> {
+        self.next_token();
+
+        let mut fields = Vec::new();
+        while !self.peek_token_is(Token::Rbrace) {
+            let field = self.expect_peek_identifier()?;
+            if !self.expect_peek(Token::Colon) {
+                return None;
+            }
+            self.next_token();
+            let value =
+                self.with_struct_literals(true, |p| p.parse_expression(Precedence::Lowest))?;
+            fields.push((field, value));
+
+            if self.peek_token_is(Token::Comma) {
+                self.next_token();
+            } else if !self.peek_token_is(Token::Rbrace) {
+                self.error_peek(
+                    ParseErrorKind::UnexpectedToken,
+                    vec![Token::Comma, Token::Rbrace],
+                );
+                return None;
+            }
+        }
+        self.next_token();
+        let span = name.span.to(self.cur_span);
+
+        Some(Expression::Struct(name, fields, span))
+    }
+
+    fn parse_field_expression(&mut self, value: Expression) -> Option<Expression> {
+        let field = self.expect_peek_identifier()?;
+        let span = value.span().to(field.span);
+
+        Some(Expression::Field(Box::new(value), field, span))
+    }
+
+    fn parse_prefix_expression(&mut self) -> Option<Expression> {
+        let start = self.cur_span;
+        let operator = match self.cur_token {
+            Token::Bang => UnaryOperator::Not,
+            Token::Minus => UnaryOperator::Minus,
+            _ => return None,
+        };
+
+        self.next_token();
+
+        self.parse_expression(Precedence::Prefix).map(|expr| {
+            let span = start.to(expr.span());
+            Expression::UnaryOperation(operator, Box::new(expr), span)
+        })
+    }
+
+    fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+        let name = match function {
+            Expression::Identifier(name) => name,
+            function => {
+                self.errors.push(ParseError {
+                    kind: ParseErrorKind::InvalidCallTarget,
+                    span: function.span(),
+                    found: self.cur_token.clone(),
+                    expected: vec![],
+                });
+                return None;
+            }
+        };
+
+        let args = self.parse_expression_list(Token::Rparen)?;
+        let span = name.span.to(self.cur_span);
+
+        Some(Expression::Call(name, args, span))
+    }
+
+    fn parse_array_expression(&mut self) -> Option<Expression> {
+        let start = self.cur_span;
+        let elements = self.parse_expression_list(Token::Rbracket)?;
+
+        Some(Expression::Array(elements, start.to(self.cur_span)))
+    }
+
+    fn parse_index_expression(&mut self, array: Expression) -> Option<Expression> {
+        self.next_token();
+        let index = self.with_struct_literals(true, |p| p.parse_expression(Precedence::Lowest))?;
+
+        if !self.expect_peek(Token::Rbracket) {
+            return None;
+        }
+        let span = array.span().to(self.cur_span);
+
+        Some(Expression::Index(Box::new(array), Box::new(index), span))
+    }
+
+    /// Parses comma separated expressions up to `end`, leaving `end` as the current token.
+    fn parse_expression_list(&mut self, end: Token) -> Option<Vec<Expression>> {
+        self.with_struct_literals(true, |p| p.parse_expression_list_inner(end))
+    }
+
+    fn parse_expression_list_inner(&mut self, end: Token) -> Option<Vec<Expression>> {
+        let mut list = Vec::new();
+
+        if self.peek_token_is(end.clone()) {
+            self.next_token();
+            return Some(list);
+        }
+
+        self.next_token();
+        list.push(self.parse_expression(Precedence::Lowest)?);
+
+        while self.peek_token_is(Token::Comma) {
+            self.next_token();
+            self.next_token();
+            list.push(self.parse_expression(Precedence::Lowest)?);
+        }
+
+        if !self.peek_token_is(end.clone()) {
+            self.error_peek(ParseErrorKind::UnexpectedToken, vec![Token::Comma, end]);
+            return None;
+        }
+        self.next_token();
+
+        Some(list)
+    }
+
+    fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
+        let operator = match self.cur_token {
+            Token::Plus => BinaryOperator::Plus,
+            Token::Minus => BinaryOperator::Minus,
+            Token::Asterisk => BinaryOperator::Multiply,
+            Token::Slash => BinaryOperator::Divide,
+            Token::Percent => BinaryOperator::Modulo,
+            Token::Equal => BinaryOperator::Equal,
+            Token::NotEqual => BinaryOperator::NotEqual,
+            Token::LessThan => BinaryOperator::LessThan,
+            Token::GreaterThan => BinaryOperator::GreaterThan,
+            Token::LessEqual => BinaryOperator::LessEqual,
+            Token::GreaterEqual => BinaryOperator::GreaterEqual,
+            Token::And => BinaryOperator::And,
+            Token::Or => BinaryOperator::Or,
+            Token::Caret => BinaryOperator::Xor,
+            Token::Ampersand => BinaryOperator::BitAnd,
+            Token::Pipe => BinaryOperator::BitOr,
+            Token::ShiftLeft => BinaryOperator::ShiftLeft,
+            Token::ShiftRight => BinaryOperator::ShiftRight,
+            _ => return None,
+        };
+
+        let precedence = self.cur_token_precedence();
+
+        self.next_token();
+
+        self.parse_expression(precedence).map(|expr| {
+            let span = left.span().to(expr.span());
+            Expression::BinaryOperation(operator, Box::new(left), Box::new(expr), span)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::ast::{
+        BinaryOperator, Block, Expression, Function, Identifier, Literal, Parameter, Pattern,
+        Program, Statement, Struct, StructField, Type, Visibility,
+    };
+    use crate::lexer::lexer::Lexer;
+    use crate::parser::parser::{ParseError, ParseErrorKind, Parser, MAX_NESTING_DEPTH};
+    use crate::token::token::{Span, Token};
+
+    fn parse_with_spans(input: &str) -> Program {
+        Parser::new(Lexer::new(input)).parse_program().unwrap()
+    }
+
+    /// The parsed program with every span reset, to compare against trees
+    /// built with `Span::default()`.
+    fn check_parse_errors(result: Result<Program, Vec<ParseError>>) -> Program {
+        let errors = match result {
+            Ok(mut program) => {
+                strip_statements(&mut program);
+                return program;
+            }
+            Err(errors) => errors,
+        };
+
+        println!("\n");
+        println!("parser has {} errors", errors.len());
+
+        for error in errors {
+            println!("parse error: {:?}", error);
+        }
+
+        println!("\n");
+        panic!("failed");
+    }
+
+    fn strip_statements(statements: &mut [Statement]) {
+        for statement in statements {
+            strip_statement(statement);
+        }
+    }
+
+    fn strip_statement(statement: &mut Statement) {
+        match statement {
+            Statement::Let {
+                pattern,
+                ty,
+                value,
+                span,
+                ..
+            } => {
+                strip_pattern(pattern);
+                ty.iter_mut().for_each(strip_type);
+                strip_expression(value);
+                *span = Span::default();
+            }
+            Statement::Assign {
+                target,
+                value,
+                span,
+                ..
+            } => {
+                target.span = Span::default();
+                strip_expression(value);
+                *span = Span::default();
+            }
+            Statement::Return(value, span)
+            | Statement::Assert(value, _, span)
+            | Statement::Commit(value, span)
+            | Statement::Expression(value, span) => {
+                strip_expression(value);
+                *span = Span::default();
+            }
+            Statement::Ifelse(condition, then_block, else_block, span) => {
+                strip_expression(condition);
+                strip_statements(&mut then_block.0);
+                strip_statements(&mut else_block.0);
+                *span = Span::default();
+            }
+            Statement::Function(function) => {
+                function.name.span = Span::default();
+                for param in &mut function.params {
+                    param.name.span = Span::default();
+                    param.ty.iter_mut().for_each(strip_type);
+                }
+                strip_statements(&mut function.body.0);
+                function.span = Span::default();
+            }
+            Statement::Struct(declaration) => {
+                declaration.name.span = Span::default();
+                for field in &mut declaration.fields {
+                    field.name.span = Span::default();
+                    strip_type(&mut field.ty);
+                }
+                declaration.span = Span::default();
+            }
+            Statement::Const { name, value, span } => {
+                name.span = Span::default();
+                strip_expression(value);
+                *span = Span::default();
+            }
+            Statement::Import { span, .. } => *span = Span::default(),
+            Statement::Module(module) => {
+                strip_statements(&mut module.program);
+                module.span = Span::default();
+            }
+            Statement::Input { name, ty, span, .. } => {
+                name.span = Span::default();
+                strip_type(ty);
+                *span = Span::default();
+            }
+            Statement::For {
+                var,
+                start,
+                end,
+                body,
+                span,
+            } => {
+                var.span = Span::default();
+                strip_expression(start);
+                strip_expression(end);
+                strip_statements(&mut body.0);
+                *span = Span::default();
+            }
+        }
+    }
+
+    fn strip_pattern(pattern: &mut Pattern) {
+        match pattern {
+            Pattern::Identifier(identifier) => identifier.span = Span::default(),
+            Pattern::Tuple(patterns, span) => {
+                patterns.iter_mut().for_each(strip_pattern);
+                *span = Span::default();
+            }
+        }
+    }
+
+    fn strip_type(ty: &mut Type) {
+        match ty {
+            Type::Array(element, len) => {
+                strip_type(element);
+                strip_expression(len);
+            }
+            Type::Tuple(elements) => elements.iter_mut().for_each(strip_type),
+            Type::Named(name) => name.span = Span::default(),
+            Type::Field | Type::Bool | Type::U8 | Type::U32 | Type::U64 => {}
+        }
+    }
+
+    fn strip_expression(expression: &mut Expression) {
+        match expression {
+            Expression::Identifier(identifier) => identifier.span = Span::default(),
+            Expression::Literal(_, span) => *span = Span::default(),
+            Expression::BinaryOperation(_, lhs, rhs, span) | Expression::Index(lhs, rhs, span) => {
+                strip_expression(lhs);
+                strip_expression(rhs);
+                *span = Span::default();
+            }
+            Expression::UnaryOperation(_, operand, span) => {
+                strip_expression(operand);
+                *span = Span::default();
+            }
+            Expression::Call(name, args, span) => {
+                name.span = Span::default();
+                args.iter_mut().for_each(strip_expression);
+                *span = Span::default();
+            }
+            Expression::If(condition, then_block, else_block, span) => {
+                strip_expression(condition);
+                strip_statements(&mut then_block.0);
+                strip_statements(&mut else_block.0);
+                *span = Span::default();
+            }
+            Expression::Array(elements, span) | Expression::Tuple(elements, span) => {
+                elements.iter_mut().for_each(strip_expression);
+                *span = Span::default();
+            }
+            Expression::Struct(name, fields, span) => {
+                name.span = Span::default();
+                for (field, value) in fields {
+                    field.span = Span::default();
+                    strip_expression(value);
+                }
+                *span = Span::default();
+            }
+            Expression::Field(value, field, span) => {
+                strip_expression(value);
+                field.span = Span::default();
+                *span = Span::default();
+            }
+        }
+    }
+
+    fn ident(name: &str) -> Identifier {
+        Identifier {
+            name: String::from(name),
+            span: Span::default(),
+            binding: None,
+        }
+    }
+
+    fn param(name: &str) -> Parameter {
+        Parameter {
+            name: ident(name),
+            ty: None,
+        }
+    }
+
+    fn int(value: i64) -> Expression {
+        Expression::Literal(Literal::Int(value.into()), Span::default())
+    }
+
+    fn let_(name: &str, value: Expression) -> Statement {
+        Statement::Let {
+            pattern: Pattern::Identifier(ident(name)),
+            mutable: false,
+            ty: None,
+            value,
+            span: Span::default(),
+        }
+    }
+
+    fn binary(op: BinaryOperator, lhs: Expression, rhs: Expression) -> Expression {
+        Expression::BinaryOperation(op, Box::new(lhs), Box::new(rhs), Span::default())
     }
 
     #[test]
@@ -419,40 +1458,713 @@ mod tests {
         let l = Lexer::new(input);
         let mut p = Parser::new(l);
 
-        let program = p.parse_program();
-        check_parse_errors(&mut p);
+        let program = check_parse_errors(p.parse_program());
+        let sp = Span::default();
         assert_eq!(
             vec![
-                Statement::Let(
-                    Identifier(String::from("x")),
-                    Expression::Literal(Literal::Int(5))
-                ),
+                let_("x", int(5)),
                 Statement::Ifelse(
-                    Expression::BinaryOperation(
+                    binary(
                         BinaryOperator::Equal,
-                        Box::new(Expression::Identifier(Identifier(String::from("x")))),
-                        Box::new(Expression::Literal(Literal::Int(5)))
+                        Expression::Identifier(ident("x")),
+                        int(5)
                     ),
                     Block(vec![
                         Statement::Ifelse(
-                            Expression::BinaryOperation(
+                            binary(
                                 BinaryOperator::Equal,
-                                Box::new(Expression::Identifier(Identifier(String::from("x")))),
-                                Box::new(Expression::Literal(Literal::Int(2)))
+                                Expression::Identifier(ident("x")),
+                                int(2)
                             ),
-                            Block(vec![Statement::Return(Expression::Literal(Literal::Int(
-                                0
-                            )))]),
-                            Block(vec![])
+                            Block(vec![Statement::Return(int(0), sp)]),
+                            Block(vec![]),
+                            sp
                         ),
-                        Statement::Return(Expression::Literal(Literal::Int(1)))
+                        Statement::Return(int(1), sp)
                     ]),
-                    Block(vec![Statement::Return(Expression::Literal(Literal::Int(
-                        2
-                    )))])
+                    Block(vec![Statement::Return(int(2), sp)]),
+                    sp
                 )
             ],
             program
         );
     }
+
+    #[test]
+    fn test_operator_precedence() {
+        let input = "a || b && c == d | e ^ f & g << 1 + 2 % 3; x <= y >= z;";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let var = |name| Expression::Identifier(ident(name));
+        let shift = binary(
+            BinaryOperator::ShiftLeft,
+            var("g"),
+            binary(
+                BinaryOperator::Plus,
+                int(1),
+                binary(BinaryOperator::Modulo, int(2), int(3)),
+            ),
+        );
+        let bits = binary(
+            BinaryOperator::BitOr,
+            var("d"),
+            binary(
+                BinaryOperator::Xor,
+                var("e"),
+                binary(BinaryOperator::BitAnd, var("f"), shift),
+            ),
+        );
+        let expected = binary(
+            BinaryOperator::Or,
+            var("a"),
+            binary(
+                BinaryOperator::And,
+                var("b"),
+                binary(BinaryOperator::Equal, var("c"), bits),
+            ),
+        );
+        let comparison = binary(
+            BinaryOperator::GreaterEqual,
+            binary(BinaryOperator::LessEqual, var("x"), var("y")),
+            var("z"),
+        );
+
+        assert_eq!(
+            program,
+            vec![
+                Statement::Expression(expected, Span::default()),
+                Statement::Expression(comparison, Span::default())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_declaration_and_call() {
+        let input = "function add(a, b) { return a + b; } add(1, add(2, 3));";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let var = |name| Expression::Identifier(ident(name));
+        let call = |args| Expression::Call(ident("add"), args, Span::default());
+        assert_eq!(
+            program,
+            vec![
+                Statement::Function(Function {
+                    name: ident("add"),
+                    params: vec![param("a"), param("b")],
+                    body: Block(vec![Statement::Return(
+                        binary(BinaryOperator::Plus, var("a"), var("b")),
+                        Span::default()
+                    )]),
+                    span: Span::default(),
+                }),
+                Statement::Expression(
+                    call(vec![int(1), call(vec![int(2), int(3)])]),
+                    Span::default()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "let x = 5;\nlet y = -x + 10;";
+        let program = parse_with_spans(input);
+
+        let span = program[1].span();
+        assert_eq!(&input[span.start..span.end], "let y = -x + 10;");
+        assert_eq!((span.line, span.column), (2, 1));
+
+        match &program[1] {
+            Statement::Let {
+                pattern: Pattern::Identifier(name),
+                value,
+                ..
+            } => {
+                assert_eq!((name.span.line, name.span.column), (2, 5));
+                let span = value.span();
+                assert_eq!(&input[span.start..span.end], "-x + 10");
+            }
+            statement => panic!("expected let statement, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_comments_are_skipped() {
+        let input = r#"
+        /// The answer.
+        let x = 6 /* six */ * 7; // forty-two
+        "#;
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+        assert_eq!(
+            program,
+            vec![let_("x", binary(BinaryOperator::Multiply, int(6), int(7)))]
+        );
+    }
+
+    #[test]
+    fn test_malformed_number_is_reported() {
+        let mut p = Parser::new(Lexer::new("let x = 0x;"));
+        let errors = p.parse_program().unwrap_err();
+        assert!(matches!(errors[0].kind, ParseErrorKind::IllegalToken(_)));
+        assert_eq!((errors[0].span.start, errors[0].span.end), (8, 10));
+    }
+
+    #[test]
+    fn test_error_span() {
+        let mut p = Parser::new(Lexer::new("let x = 1;\nlet y 2;"));
+        let errors = p.parse_program().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].span.line, errors[0].span.column), (2, 7));
+    }
+
+    #[test]
+    fn test_recovers_at_statement_boundaries() {
+        let input = r#"
+        let x = ;
+        let y = 2;
+        let = 3;
+        function f(a) {
+            return + 1;
+            let z = a;
+        }
+        return y
+        "#;
+
+        let mut p = Parser::new(Lexer::new(input));
+        let errors = p.parse_program().unwrap_err();
+
+        let lines: Vec<usize> = errors.iter().map(|error| error.span.line).collect();
+        assert_eq!(lines, vec![2, 4, 6, 10]);
+    }
+
+    #[test]
+    fn test_always_terminates() {
+        let inputs = vec![
+            "let x = 1",
+            "return",
+            "assert x",
+            "if x { let y = 1;",
+            "if x { } else",
+            "function f(a, { }",
+            "f(1, 2",
+            "+ 1;",
+            ") ) } }",
+            "let let let",
+        ];
+        for input in inputs {
+            let mut p = Parser::new(Lexer::new(input));
+            assert!(p.parse_program().is_err(), "{}", input);
+        }
+
+        let deep = "(".repeat(MAX_NESTING_DEPTH * 4);
+        let mut p = Parser::new(Lexer::new(&deep));
+        assert!(p.parse_program().is_err());
+
+        // Every short program over a small alphabet of tokens
+        let alphabet = [
+            "let", "x", "=", "1", ";", "(", ")", "{", "}", "if", "else", "+", ",",
+        ];
+        let mut programs = vec![String::new()];
+        for _ in 0..4 {
+            programs = programs
+                .iter()
+                .flat_map(|program| {
+                    alphabet
+                        .iter()
+                        .map(move |tok| format!("{} {}", program, tok))
+                })
+                .collect();
+            for program in programs.iter() {
+                let _ = Parser::new(Lexer::new(program)).parse_program();
+            }
+        }
+    }
+
+    #[test]
+    fn test_structured_errors() {
+        let parse_errors = |input| Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+
+        let errors = parse_errors("let x = 1\nlet y = 2;");
+        assert_eq!(errors[0].kind, ParseErrorKind::MissingSemicolon);
+        assert_eq!(errors[0].found, Token::Let);
+        assert_eq!(errors[0].expected, vec![Token::Semicolon]);
+        assert_eq!(
+            errors[0].to_string(),
+            "2:1: missing semicolon: expected `;`, found `let`"
+        );
+
+        let errors = parse_errors("1 + 2 3;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ParseErrorKind::MissingSemicolon);
+        assert_eq!(errors[0].found, Token::Int(3.into()));
+        assert_eq!(
+            errors[0].to_string(),
+            "1:7: missing semicolon: expected `;`, found `3`"
+        );
+        // The value at the end of a block needs no `;`.
+        assert!(
+            Parser::new(Lexer::new("let y = if x { 1 + 2 } else { 3 };"))
+                .parse_program()
+                .is_ok()
+        );
+
+        let errors = parse_errors("if x {\n  let y = 1;");
+        assert_eq!(errors[0].kind, ParseErrorKind::UnclosedBlock);
+        assert_eq!((errors[0].span.line, errors[0].span.column), (1, 6));
+        assert_eq!(
+            errors[0].to_string(),
+            "1:6: unclosed block: expected `}`, found end of file"
+        );
+
+        let errors = parse_errors("f(1) = 2;");
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidAssignmentTarget);
+        assert_eq!((errors[0].span.start, errors[0].span.end), (0, 4));
+
+        let errors = parse_errors("let x = ;");
+        assert_eq!(errors[0].kind, ParseErrorKind::ExpectedExpression);
+        assert_eq!(errors[0].to_string(), "1:9: expected expression, found `;`");
+
+        let errors = parse_errors("f(1 2);");
+        assert_eq!(errors[0].expected, vec![Token::Comma, Token::Rparen]);
+        assert_eq!(
+            errors[0].to_string(),
+            "1:5: unexpected token: expected `,` or `)`, found `2`"
+        );
+    }
+
+    #[test]
+    fn test_else_block() {
+        let input = "if x { let y = 1; } else { let z = 2; return z; }";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+        assert_eq!(
+            program,
+            vec![Statement::Ifelse(
+                Expression::Identifier(ident("x")),
+                Block(vec![let_("y", int(1))]),
+                Block(vec![
+                    let_("z", int(2)),
+                    Statement::Return(Expression::Identifier(ident("z")), Span::default())
+                ]),
+                Span::default()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let input = "if a { 1; } else if b { 2; } else { 3; }";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+        let sp = Span::default();
+        assert_eq!(
+            program,
+            vec![Statement::Ifelse(
+                Expression::Identifier(ident("a")),
+                Block(vec![Statement::Expression(int(1), sp)]),
+                Block(vec![Statement::Ifelse(
+                    Expression::Identifier(ident("b")),
+                    Block(vec![Statement::Expression(int(2), sp)]),
+                    Block(vec![Statement::Expression(int(3), sp)]),
+                    sp
+                )]),
+                sp
+            )]
+        );
+    }
+
+    #[test]
+    fn test_if_expression() {
+        let input = "let y = if a { 1 } else if b { 2 } else { 3 } + 4;";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+        let sp = Span::default();
+        let inner = Expression::If(
+            Box::new(Expression::Identifier(ident("b"))),
+            Block(vec![Statement::Expression(int(2), sp)]),
+            Block(vec![Statement::Expression(int(3), sp)]),
+            sp,
+        );
+        let outer = Expression::If(
+            Box::new(Expression::Identifier(ident("a"))),
+            Block(vec![Statement::Expression(int(1), sp)]),
+            Block(vec![Statement::Expression(inner, sp)]),
+            sp,
+        );
+        assert_eq!(
+            program,
+            vec![let_("y", binary(BinaryOperator::Plus, outer, int(4)))]
+        );
+
+        // An `if` with an `else` at the end of a branch is that branch's value.
+        let mut p = Parser::new(Lexer::new(
+            "let y = if a { if b { 1 } else { 2 } } else { 3 };",
+        ));
+        let program = check_parse_errors(p.parse_program());
+        let inner = Expression::If(
+            Box::new(Expression::Identifier(ident("b"))),
+            Block(vec![Statement::Expression(int(1), sp)]),
+            Block(vec![Statement::Expression(int(2), sp)]),
+            sp,
+        );
+        let outer = Expression::If(
+            Box::new(Expression::Identifier(ident("a"))),
+            Block(vec![Statement::Expression(inner, sp)]),
+            Block(vec![Statement::Expression(int(3), sp)]),
+            sp,
+        );
+        assert_eq!(program, vec![let_("y", outer)]);
+
+        let errors = Parser::new(Lexer::new("let y = if a { 1 };"))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(errors[0].expected, vec![Token::Else]);
+    }
+
+    #[test]
+    fn test_assignment() {
+        let input = "let mut acc = 0; acc = acc + 1; acc += 2; acc -= 3; acc *= 4;";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        let assign = |op, value| Statement::Assign {
+            target: ident("acc"),
+            op,
+            value,
+            span: sp,
+        };
+        assert_eq!(
+            program,
+            vec![
+                Statement::Let {
+                    pattern: Pattern::Identifier(ident("acc")),
+                    mutable: true,
+                    ty: None,
+                    value: int(0),
+                    span: sp
+                },
+                assign(
+                    None,
+                    binary(
+                        BinaryOperator::Plus,
+                        Expression::Identifier(ident("acc")),
+                        int(1)
+                    )
+                ),
+                assign(Some(BinaryOperator::Plus), int(2)),
+                assign(Some(BinaryOperator::Minus), int(3)),
+                assign(Some(BinaryOperator::Multiply), int(4)),
+            ]
+        );
+
+        let errors = Parser::new(Lexer::new("f(x) += 1;"))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidAssignmentTarget);
+    }
+
+    #[test]
+    fn test_for_statement() {
+        let input = "for i in 0..n + 1 { acc += i; }";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        assert_eq!(
+            program,
+            vec![Statement::For {
+                var: ident("i"),
+                start: int(0),
+                end: binary(
+                    BinaryOperator::Plus,
+                    Expression::Identifier(ident("n")),
+                    int(1)
+                ),
+                body: Block(vec![Statement::Assign {
+                    target: ident("acc"),
+                    op: Some(BinaryOperator::Plus),
+                    value: Expression::Identifier(ident("i")),
+                    span: sp
+                }]),
+                span: sp
+            }]
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let input =
+            "function root(path: [[field; 2]; DEPTH], leaf) { return path[0][i] + [1, 2][leaf]; }";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let var = |name| Expression::Identifier(ident(name));
+        let index =
+            |array, index| Expression::Index(Box::new(array), Box::new(index), Span::default());
+        let path_ty = Type::Array(
+            Box::new(Type::Array(Box::new(Type::Field), Box::new(int(2)))),
+            Box::new(var("DEPTH")),
+        );
+        assert_eq!(
+            program,
+            vec![Statement::Function(Function {
+                name: ident("root"),
+                params: vec![
+                    Parameter {
+                        name: ident("path"),
+                        ty: Some(path_ty),
+                    },
+                    param("leaf"),
+                ],
+                body: Block(vec![Statement::Return(
+                    binary(
+                        BinaryOperator::Plus,
+                        index(index(var("path"), int(0)), var("i")),
+                        index(
+                            Expression::Array(vec![int(1), int(2)], Span::default()),
+                            var("leaf")
+                        ),
+                    ),
+                    Span::default()
+                )]),
+                span: Span::default(),
+            })]
+        );
+
+        let mut p = Parser::new(Lexer::new("function f(x: 3) {}"));
+        let errors = p.parse_program().unwrap_err();
+        assert_eq!(errors[0].kind, ParseErrorKind::ExpectedType);
+        assert_eq!(errors[0].found, Token::Int(3.into()));
+    }
+
+    #[test]
+    fn test_tuples_and_structs() {
+        let input = "struct Point { x: field, y: (field, Point) }
+        let (a, (b, c)) = (p.x, Point { x: 1, y: (2, q) }.y, ());
+        if flag { p }";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        let var = |name| Expression::Identifier(ident(name));
+        let field = |value, name| Expression::Field(Box::new(value), ident(name), sp);
+        let pattern = |name| Pattern::Identifier(ident(name));
+        let point = Expression::Struct(
+            ident("Point"),
+            vec![
+                (ident("x"), int(1)),
+                (ident("y"), Expression::Tuple(vec![int(2), var("q")], sp)),
+            ],
+            sp,
+        );
+        assert_eq!(
+            program,
+            vec![
+                Statement::Struct(Struct {
+                    name: ident("Point"),
+                    fields: vec![
+                        StructField {
+                            name: ident("x"),
+                            ty: Type::Field,
+                        },
+                        StructField {
+                            name: ident("y"),
+                            ty: Type::Tuple(vec![Type::Field, Type::Named(ident("Point"))]),
+                        },
+                    ],
+                    span: sp,
+                }),
+                Statement::Let {
+                    pattern: Pattern::Tuple(
+                        vec![
+                            pattern("a"),
+                            Pattern::Tuple(vec![pattern("b"), pattern("c")], sp)
+                        ],
+                        sp
+                    ),
+                    mutable: false,
+                    ty: None,
+                    value: Expression::Tuple(
+                        vec![
+                            field(var("p"), "x"),
+                            field(point, "y"),
+                            Expression::Tuple(vec![], sp)
+                        ],
+                        sp
+                    ),
+                    span: sp,
+                },
+                // `flag {` is a condition followed by a block, not a struct literal.
+                Statement::Ifelse(
+                    var("flag"),
+                    Block(vec![Statement::Expression(var("p"), sp)]),
+                    Block(vec![]),
+                    sp
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_input_declarations() {
+        let input = "public root: field;\nprivate path: [bool; 4];";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        assert_eq!(
+            program,
+            vec![
+                Statement::Input {
+                    visibility: Visibility::Public,
+                    name: ident("root"),
+                    ty: Type::Field,
+                    span: sp,
+                },
+                Statement::Input {
+                    visibility: Visibility::Private,
+                    name: ident("path"),
+                    ty: Type::Array(Box::new(Type::Bool), Box::new(int(4))),
+                    span: sp,
+                },
+            ]
+        );
+        let span = parse_with_spans(input)[1].span();
+        assert_eq!(&input[span.start..span.end], "private path: [bool; 4];");
+    }
+
+    #[test]
+    fn test_commit_statement() {
+        let input = "commit(x + 1);";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        assert_eq!(
+            program,
+            vec![Statement::Commit(
+                binary(
+                    BinaryOperator::Plus,
+                    Expression::Identifier(ident("x")),
+                    int(1)
+                ),
+                sp
+            )]
+        );
+        assert_eq!(parse_with_spans(input)[0].span().end, input.len());
+    }
+
+    #[test]
+    fn test_assert_statement() {
+        let input = "assert x;\nassert(x, \"x must be set\");\nassert (x) == 1;
+            assert (x, 1) == (1, x);";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        let x = || Expression::Identifier(ident("x"));
+        assert_eq!(
+            program,
+            vec![
+                Statement::Assert(x(), None, sp),
+                Statement::Assert(x(), Some(String::from("x must be set")), sp),
+                Statement::Assert(binary(BinaryOperator::Equal, x(), int(1)), None, sp),
+                Statement::Assert(
+                    binary(
+                        BinaryOperator::Equal,
+                        Expression::Tuple(vec![x(), int(1)], sp),
+                        Expression::Tuple(vec![int(1), x()], sp)
+                    ),
+                    None,
+                    sp
+                ),
+            ]
+        );
+
+        let errors = Parser::new(Lexer::new("assert(x, \"message\", y);"))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(errors[0].found, Token::Comma);
+    }
+
+    #[test]
+    fn test_const_statement() {
+        let input = "const DEPTH = 1 + 2;";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        assert_eq!(
+            program,
+            vec![Statement::Const {
+                name: ident("DEPTH"),
+                value: binary(BinaryOperator::Plus, int(1), int(2)),
+                span: Span::default(),
+            }]
+        );
+        assert_eq!(parse_with_spans(input)[0].span().end, input.len());
+    }
+
+    #[test]
+    fn test_import_and_paths() {
+        let input =
+            "import \"lib/hash.zk\";\nlet s: hash::State = hash::State { a: hash::permute(x) };";
+
+        let mut p = Parser::new(Lexer::new(input));
+        let program = check_parse_errors(p.parse_program());
+
+        let sp = Span::default();
+        assert_eq!(
+            program,
+            vec![
+                Statement::Import {
+                    path: String::from("lib/hash.zk"),
+                    span: sp,
+                },
+                Statement::Let {
+                    pattern: Pattern::Identifier(ident("s")),
+                    mutable: false,
+                    ty: Some(Type::Named(ident("hash::State"))),
+                    value: Expression::Struct(
+                        ident("hash::State"),
+                        vec![(
+                            ident("a"),
+                            Expression::Call(
+                                ident("hash::permute"),
+                                vec![Expression::Identifier(ident("x"))],
+                                sp
+                            )
+                        )],
+                        sp
+                    ),
+                    span: sp,
+                },
+            ]
+        );
+        let Statement::Let {
+            ty: Some(Type::Named(name)),
+            ..
+        } = &parse_with_spans(input)[1]
+        else {
+            unreachable!()
+        };
+        assert_eq!(&input[name.span.start..name.span.end], "hash::State");
+    }
 }
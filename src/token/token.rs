@@ -1,39 +1,194 @@
+use std::fmt;
+
+use num_bigint::BigInt;
+
+use crate::lexer::lexer::LexError;
+
+/// Location of a piece of source text: a byte range plus the 1-based line and
+/// column of its first byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Span covering `self` through the end of `end`.
+    pub fn to(self, end: Span) -> Span {
+        Span {
+            end: end.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
-    Illegal,
+    Illegal(LexError),
     Eof,
 
+    // Trivia
+    /// `// ...` or `/* ... */`, including the delimiters.
+    Comment(String),
+    /// `/// ...`, without the leading slashes.
+    DocComment(String),
+
     // Identifiers + Literals
     Ident(String),
-    Int(i64),
+    Int(BigInt),
     Bool(bool),
+    /// `"..."`, with escapes already processed.
+    Str(String),
 
     // Operators
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
     Plus,
     Minus,
     Bang,
     Asterisk,
     Slash,
+    Percent,
+    Caret,
+    Ampersand,
+    Pipe,
+    ShiftLeft,
+    ShiftRight,
 
     Equal,
     NotEqual,
     LessThan,
     GreaterThan,
+    LessEqual,
+    GreaterEqual,
+    And,
+    Or,
 
     // Delimiters
+    Dot,
+    DotDot,
     Comma,
+    Colon,
+    ColonColon,
     Semicolon,
     Lparen,
     Rparen,
     Lbrace,
     Rbrace,
+    Lbracket,
+    Rbracket,
 
     // Reserved Keywords
     Function,
     Let,
+    Mut,
     If,
     Else,
     Return,
     Assert,
+    Commit,
+    For,
+    In,
+    Struct,
+    Const,
+    Import,
+    Public,
+    Private,
+}
+
+impl Token {
+    /// `=` and the compound assignments
+    pub fn is_assignment(&self) -> bool {
+        matches!(
+            self,
+            Token::Assign | Token::PlusAssign | Token::MinusAssign | Token::AsteriskAssign
+        )
+    }
+
+    /// Tokens the parser skips but a formatter or doc generator still wants.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Token::Comment(_) | Token::DocComment(_))
+    }
+}
+
+impl fmt::Display for Token {
+    /// The token as it appears in source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Illegal(error) => write!(f, "{}", error),
+            Token::Eof => write!(f, "end of file"),
+            Token::Comment(text) => write!(f, "{}", text),
+            Token::DocComment(text) => write!(f, "///{}", text),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Int(value) => write!(f, "{}", value),
+            Token::Bool(value) => write!(f, "{}", value),
+            Token::Str(value) => write!(f, "{:?}", value),
+            Token::Assign => write!(f, "="),
+            Token::PlusAssign => write!(f, "+="),
+            Token::MinusAssign => write!(f, "-="),
+            Token::AsteriskAssign => write!(f, "*="),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Bang => write!(f, "!"),
+            Token::Asterisk => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Caret => write!(f, "^"),
+            Token::Ampersand => write!(f, "&"),
+            Token::Pipe => write!(f, "|"),
+            Token::ShiftLeft => write!(f, "<<"),
+            Token::ShiftRight => write!(f, ">>"),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::LessThan => write!(f, "<"),
+            Token::GreaterThan => write!(f, ">"),
+            Token::LessEqual => write!(f, "<="),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::DotDot => write!(f, ".."),
+            Token::Comma => write!(f, ","),
+            Token::Colon => write!(f, ":"),
+            Token::ColonColon => write!(f, "::"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Dot => write!(f, "."),
+            Token::Lparen => write!(f, "("),
+            Token::Rparen => write!(f, ")"),
+            Token::Lbrace => write!(f, "{{"),
+            Token::Rbrace => write!(f, "}}"),
+            Token::Lbracket => write!(f, "["),
+            Token::Rbracket => write!(f, "]"),
+            Token::Function => write!(f, "function"),
+            Token::Let => write!(f, "let"),
+            Token::Mut => write!(f, "mut"),
+            Token::If => write!(f, "if"),
+            Token::Else => write!(f, "else"),
+            Token::Return => write!(f, "return"),
+            Token::Assert => write!(f, "assert"),
+            Token::Commit => write!(f, "commit"),
+            Token::For => write!(f, "for"),
+            Token::In => write!(f, "in"),
+            Token::Struct => write!(f, "struct"),
+            Token::Const => write!(f, "const"),
+            Token::Import => write!(f, "import"),
+            Token::Public => write!(f, "public"),
+            Token::Private => write!(f, "private"),
+        }
+    }
 }
@@ -1,15 +1,36 @@
 use crate::{
     ast::ast,
-    ir::{self, Block, BlockExit, Instruction, Program},
+    field::{Bn254, Field},
+    ir::{
+        self, register::TranslateError, Block, BlockExit, BlockId, Instruction, Operand, Program,
+        Register,
+    },
+    token::token::Span,
 };
-use num_bigint::BigInt;
-use num_traits::cast::ToPrimitive;
+use num_bigint::{BigInt, Sign};
+use num_traits::{cast::ToPrimitive, One, Zero};
+use std::{collections::HashMap, rc::Rc};
 use thiserror::Error;
 
+mod register;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Unit,
-    Num { value: BigInt },
+    /// An element of the VM's field, in `0..p`
+    Num {
+        value: BigInt,
+    },
+    Array {
+        elements: Vec<Value>,
+    },
+    Tuple {
+        elements: Vec<Value>,
+    },
+    Struct {
+        name: String,
+        fields: Vec<(String, Value)>,
+    },
 }
 
 impl Value {
@@ -17,11 +38,56 @@ impl Value {
         match self {
             Value::Unit => Err(VMError::VoidValue),
             Value::Num { value } => Ok(value),
+            _ => Err(VMError::NotANumber),
+        }
+    }
+
+    /// Appends the scalars of the value in order: elements of arrays and
+    /// tuples, then struct fields in declaration order.
+    pub fn flatten_into(self, out: &mut Vec<BigInt>) -> Result<(), VMError> {
+        match self {
+            Value::Unit => return Err(VMError::VoidValue),
+            Value::Num { value } => out.push(value),
+            Value::Array { elements } | Value::Tuple { elements } => {
+                for element in elements {
+                    element.flatten_into(out)?;
+                }
+            }
+            Value::Struct { fields, .. } => {
+                for (_, value) in fields {
+                    value.flatten_into(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_array(self) -> Result<Vec<Value>, VMError> {
+        match self {
+            Value::Array { elements } => Ok(elements),
+            _ => Err(VMError::NotAnArray),
         }
     }
 
+    pub fn get_tuple(self) -> Result<Vec<Value>, VMError> {
+        match self {
+            Value::Tuple { elements } => Ok(elements),
+            _ => Err(VMError::NotATuple),
+        }
+    }
+
+    /// Booleans are 0 or 1; anything else is an error rather than truthy.
     pub fn is_true(self) -> Result<bool, VMError> {
-        todo!();
+        num_to_bool(self.get_num()?)
+    }
+}
+
+impl From<ir::Constant> for Value {
+    fn from(constant: ir::Constant) -> Self {
+        match constant {
+            ir::Constant::Unit => Value::Unit,
+            ir::Constant::Num { value } => Value::Num { value },
+        }
     }
 }
 
@@ -31,17 +97,136 @@ pub enum VMError {
     StackUnderflow,
     #[error("Unit to Num")]
     VoidValue,
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("invalid shift amount {0}")]
+    InvalidShift(BigInt),
+    #[error("reached unreachable block")]
+    Unreachable,
+    #[error("load from uninitialized address {0}")]
+    UninitializedMemory(usize),
+    #[error("undefined function `{0}`")]
+    UndefinedFunction(String),
+    #[error("call depth exceeded {}", MAX_CALL_DEPTH)]
+    CallDepthExceeded,
+    #[error("expected a number")]
+    NotANumber,
+    #[error("expected an array")]
+    NotAnArray,
+    #[error("expected a boolean, found {0}")]
+    NotABoolean(BigInt),
+    #[error("{value} does not fit in {bits} bits")]
+    RangeCheckFailed { value: BigInt, bits: u32 },
+    #[error("{0} input tape is exhausted")]
+    MissingInput(ast::Visibility),
+    #[error("expected a tuple")]
+    NotATuple,
+    #[error("expected a struct")]
+    NotAStruct,
+    #[error("cannot unpack a tuple of {found} elements into {expected}")]
+    UnpackMismatch { expected: usize, found: usize },
+    #[error("struct `{name}` has no field `{field}`")]
+    UnknownField { name: String, field: String },
+    #[error(
+        "{span}: assertion failed{}",
+        message.as_ref().map(|message| format!(": {}", message)).unwrap_or_default()
+    )]
+    AssertionFailed {
+        message: Option<String>,
+        /// The failing `Assert` instruction is `ip` in `block`
+        block: BlockId,
+        ip: usize,
+        span: Span,
+    },
+    #[error("index {index} out of bounds for array of length {len}")]
+    IndexOutOfBounds { index: BigInt, len: usize },
+    #[error("jump to nonexistent block {0}")]
+    InvalidJumpTarget(BlockId),
+    #[error("register operands need the register VM")]
+    RegisterOperand,
+    #[error("stack operands need the stack VM")]
+    StackOperand,
+    #[error("read of unset register {0:?}")]
+    UninitializedRegister(Register),
+    #[error(transparent)]
+    Translate(#[from] TranslateError),
 }
 
+pub const MAX_CALL_DEPTH: usize = 1024;
+
 pub struct VM {
-    // instruction pointer
+    /// The running block of the current function
+    pub block: BlockId,
+    // instruction pointer, within `block`
     pub ip: usize,
-    pub program: Program,
+    /// Shared so that running a function can borrow it while the VM changes.
+    pub program: Rc<Program>,
     pub data: Vec<Value>,
     pub address: Vec<Value>,
+    pub frames: Vec<StackFrame>,
+    public_inputs: InputTape,
+    private_inputs: InputTape,
+    /// Public outputs: committed values and the program's return value, as
+    /// scalars. A proof binds these as public inputs.
+    pub journal: Vec<BigInt>,
+    /// All arithmetic is in this field. Values are always reduced.
+    field: Box<dyn Field>,
+}
+
+/// Values for one kind of input, read front to back.
+#[derive(Debug, Default)]
+struct InputTape {
+    values: Vec<BigInt>,
+    position: usize,
 }
 
 impl VM {
+    pub fn new(program: Program) -> Self {
+        VM {
+            block: 0,
+            ip: 0,
+            program: Rc::new(program),
+            data: vec![],
+            address: vec![],
+            frames: vec![StackFrame::new("main")],
+            public_inputs: InputTape::default(),
+            private_inputs: InputTape::default(),
+            journal: vec![],
+            field: Box::new(Bn254),
+        }
+    }
+
+    /// Computes in `field` instead of the default BN254 scalar field.
+    pub fn with_field(mut self, field: impl Field + 'static) -> Self {
+        self.field = Box::new(field);
+        self
+    }
+
+    pub fn field(&self) -> &dyn Field {
+        self.field.as_ref()
+    }
+
+    /// Sets the public and private input tapes, in the order the program reads them.
+    pub fn with_inputs(mut self, public: Vec<BigInt>, private: Vec<BigInt>) -> Self {
+        self.public_inputs.values = public;
+        self.private_inputs.values = private;
+        self
+    }
+
+    fn read_input(&mut self, visibility: ast::Visibility) -> Result<BigInt, VMError> {
+        let tape = match visibility {
+            ast::Visibility::Public => &mut self.public_inputs,
+            ast::Visibility::Private => &mut self.private_inputs,
+        };
+        let value = tape
+            .values
+            .get(tape.position)
+            .cloned()
+            .ok_or(VMError::MissingInput(visibility))?;
+        tape.position += 1;
+        Ok(value)
+    }
+
     fn pop_data(&mut self) -> Result<Value, VMError> {
         if let Some(value) = self.data.pop() {
             Ok(value)
@@ -55,20 +240,125 @@ impl VM {
         self.data.push(value);
     }
 
-    fn pop_address(&mut self) -> Result<Value, VMError> {
-        if let Some(value) = self.address.pop() {
-            Ok(value)
-        } else {
-            Err(VMError::StackUnderflow)
+    fn push_address(&mut self, value: Value) {
+        self.address.push(value);
+    }
+
+    fn frame(&mut self) -> &mut StackFrame {
+        self.frames.last_mut().expect("VM always has a frame")
+    }
+
+    fn pop_usize(&mut self) -> Result<usize, VMError> {
+        self.pop_data()?
+            .get_num()?
+            .to_usize()
+            .ok_or(VMError::VoidValue)
+    }
+
+    fn execute_nop(&mut self) {}
+
+    fn load(&mut self, address: usize) -> Result<Value, VMError> {
+        self.frame()
+            .memory
+            .get(address)
+            .cloned()
+            .flatten()
+            .ok_or(VMError::UninitializedMemory(address))
+    }
+
+    fn store(&mut self, address: usize, value: Value) {
+        let memory = &mut self.frame().memory;
+        if memory.len() <= address {
+            memory.resize(address + 1, None);
         }
+        memory[address] = Some(value);
     }
 
-    fn push_address(&mut self, value: Value) {
-        self.address.push(value);
+    fn execute_load(&mut self) -> Result<(), VMError> {
+        let address = self.pop_usize()?;
+        let value = self.load(address)?;
+        self.push_data(value);
+        Ok(())
     }
 
-    fn execute_nop(&mut self) {
-        ()
+    fn execute_store(&mut self) -> Result<(), VMError> {
+        let address = self.pop_usize()?;
+        let value = self.pop_data()?;
+        self.store(address, value);
+        Ok(())
+    }
+
+    fn execute_call(&mut self, name: &str) -> Result<ExecTrace, VMError> {
+        let program = Rc::clone(&self.program);
+        let function = program
+            .functions
+            .get(name)
+            .ok_or_else(|| VMError::UndefinedFunction(name.to_string()))?;
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(VMError::CallDepthExceeded);
+        }
+        // The stack as the caller will see it again, without the arguments.
+        let base = self
+            .data
+            .len()
+            .checked_sub(function.params)
+            .ok_or(VMError::StackUnderflow)?;
+
+        self.push_address(Value::Num {
+            value: self.block.into(),
+        });
+        self.frames.push(StackFrame::new(name));
+        let (trace, value) = self.execute_blocks(&function.blocks)?;
+        self.frames.pop();
+        // Whatever else the callee left on the stack is dropped.
+        self.data.truncate(base);
+        self.data.extend(value);
+        Ok(trace)
+    }
+
+    /// Pops the top `len` values, keeping their stack order.
+    fn pop_values(&mut self, len: usize) -> Result<Vec<Value>, VMError> {
+        if self.data.len() < len {
+            return Err(VMError::StackUnderflow);
+        }
+        Ok(self.data.split_off(self.data.len() - len))
+    }
+
+    fn execute_range_check(&mut self, bits: u32) -> Result<(), VMError> {
+        let value = self.data.last().ok_or(VMError::StackUnderflow)?;
+        range_check(value, bits)
+    }
+
+    fn execute_unpack(&mut self, len: usize) -> Result<(), VMError> {
+        let elements = unpack(self.pop_data()?, len)?;
+        self.data.extend(elements);
+        Ok(())
+    }
+
+    fn execute_make_struct(&mut self, name: &str, fields: &[String]) -> Result<(), VMError> {
+        let values = self.pop_values(fields.len())?;
+        self.push_data(make_struct(name, fields, values));
+        Ok(())
+    }
+
+    fn execute_get_field(&mut self, field: &str) -> Result<(), VMError> {
+        let value = get_field(self.pop_data()?, field)?;
+        self.push_data(value);
+        Ok(())
+    }
+
+    fn execute_get(&mut self, index: usize) -> Result<(), VMError> {
+        let value = get(self.pop_data()?, index)?;
+        self.push_data(value);
+        Ok(())
+    }
+
+    fn execute_select(&mut self) -> Result<(), VMError> {
+        let index = self.pop_data()?;
+        let array = self.pop_data()?;
+        let value = select(array, index)?;
+        self.push_data(value);
+        Ok(())
     }
 
     fn execute_swap(&mut self) -> Result<(), VMError> {
@@ -86,24 +376,84 @@ impl VM {
         Ok(())
     }
 
+    /// Applies `op` to the two values on top of the stack.
     fn execute_binop(&mut self, op: &ast::BinaryOperator) -> Result<Value, VMError> {
-        let lhs = self.pop_data()?.get_num()?;
-        let rhs = self.pop_data()?.get_num()?;
+        // The right operand was pushed last.
+        let rhs = self.pop_data()?;
+        let lhs = self.pop_data()?;
+        self.binop(op, lhs, rhs)
+    }
 
-        let result = match op {
-            ast::BinaryOperator::Plus => Value::Num { value: lhs + rhs },
-            ast::BinaryOperator::Multiply => Value::Num { value: lhs * rhs },
-            _ => Value::Unit,
+    /// Operands must be numbers, and booleans for `&&` and `||`. `+`, `-`,
+    /// `*` and `/` are field operations; comparisons, `%`, shifts and bitwise
+    /// operators work on the elements' representatives in `0..p`. Shift
+    /// amounts must be below the bit size of `p`.
+    fn binop(&self, op: &ast::BinaryOperator, lhs: Value, rhs: Value) -> Result<Value, VMError> {
+        use ast::BinaryOperator::*;
+
+        let rhs = rhs.get_num()?;
+        let lhs = lhs.get_num()?;
+
+        let field = self.field.as_ref();
+        let shift = |amount: &BigInt| {
+            amount
+                .to_usize()
+                .filter(|amount| (*amount as u64) < field.modulus().bits())
+                .ok_or(VMError::InvalidShift(amount.clone()))
         };
-        Ok(result)
+        let value = match op {
+            Plus => field.add(&lhs, &rhs),
+            Minus => field.sub(&lhs, &rhs),
+            Multiply => field.mul(&lhs, &rhs),
+            Divide => field.div(&lhs, &rhs).ok_or(VMError::DivisionByZero)?,
+            Modulo if rhs.is_zero() => return Err(VMError::DivisionByZero),
+            Modulo => lhs % rhs,
+            Equal => bool_to_num(lhs == rhs),
+            NotEqual => bool_to_num(lhs != rhs),
+            LessThan => bool_to_num(lhs < rhs),
+            GreaterThan => bool_to_num(lhs > rhs),
+            LessEqual => bool_to_num(lhs <= rhs),
+            GreaterEqual => bool_to_num(lhs >= rhs),
+            And => bool_to_num(num_to_bool(lhs)? & num_to_bool(rhs)?),
+            Or => bool_to_num(num_to_bool(lhs)? | num_to_bool(rhs)?),
+            Xor => field.reduce(&(lhs ^ rhs)),
+            BitAnd => lhs & rhs,
+            BitOr => field.reduce(&(lhs | rhs)),
+            ShiftLeft => field.reduce(&(lhs << shift(&rhs)?)),
+            ShiftRight => lhs >> shift(&rhs)?,
+        };
+        Ok(Value::Num { value })
+    }
+
+    fn execute_integer_divide(&mut self) -> Result<(), VMError> {
+        let rhs = self.pop_data()?;
+        let lhs = self.pop_data()?;
+        self.push_data(integer_divide(lhs, rhs)?);
+        Ok(())
     }
 
     fn execute_unop(&mut self, op: &ast::UnaryOperator) -> Result<Value, VMError> {
-        let result = match op {
-            ast::UnaryOperator::Minus => Value::Unit,
-            _ => Value::Unit,
+        let operand = self.pop_data()?;
+        self.unop(op, operand)
+    }
+
+    fn unop(&self, op: &ast::UnaryOperator, operand: Value) -> Result<Value, VMError> {
+        let value = match op {
+            ast::UnaryOperator::Minus => self.field.neg(&operand.get_num()?),
+            // `!` on integers is lowered to `^` with a mask, so this is a boolean.
+            ast::UnaryOperator::Not => bool_to_num(!operand.is_true()?),
         };
-        Ok(result)
+        Ok(Value::Num { value })
+    }
+
+    /// A constant as a value of the VM's field.
+    fn constant(&self, constant: &ir::Constant) -> Value {
+        match constant {
+            ir::Constant::Unit => Value::Unit,
+            ir::Constant::Num { value } => Value::Num {
+                value: self.field.reduce(value),
+            },
+        }
     }
 
     fn execute_instruction(&mut self, instruction: &Instruction) -> Result<ExecStep, VMError> {
@@ -111,6 +461,15 @@ impl VM {
             Instruction::Nop => {
                 self.execute_nop();
             }
+            Instruction::Push { value } => {
+                self.push_data(self.constant(value));
+            }
+            Instruction::IntegerDivide => {
+                self.execute_integer_divide()?;
+            }
+            Instruction::Pop => {
+                self.pop_data()?;
+            }
             Instruction::BinOp { op } => {
                 let result = self.execute_binop(op)?;
                 self.push_data(result);
@@ -125,67 +484,152 @@ impl VM {
             Instruction::Dup => {
                 self.execute_dup()?;
             }
-            _ => todo!(),
-        };
-        self.ip += 1;
-        Ok(ExecStep {
-            ip: self.ip,
-            op: StepFunction::from(instruction.clone()),
-        })
-    }
-
-    fn execute_blockexit(&mut self, exit: &BlockExit) -> Result<ExecStep, VMError> {
-        match exit {
-            BlockExit::Jump => {
-                self.ip = self
-                    .pop_data()?
-                    .get_num()?
-                    .to_usize()
-                    .ok_or(VMError::VoidValue)?;
-            }
-            BlockExit::ConditionalJump => {
-                let condition = self.pop_data()?.is_true()?;
-                let ip_then = self.pop_data()?;
-                let ip_else = self.pop_data()?;
-                if condition {
-                    self.ip = ip_then.get_num()?.to_usize().ok_or(VMError::VoidValue)?;
-                } else {
-                    self.ip = ip_else.get_num()?.to_usize().ok_or(VMError::VoidValue)?;
+            Instruction::Load => {
+                self.execute_load()?;
+            }
+            Instruction::Store => {
+                self.execute_store()?;
+            }
+            Instruction::MakeArray { len } => {
+                let elements = self.pop_values(*len)?;
+                self.push_data(Value::Array { elements });
+            }
+            Instruction::MakeTuple { len } => {
+                let elements = self.pop_values(*len)?;
+                self.push_data(Value::Tuple { elements });
+            }
+            Instruction::Unpack { len } => {
+                self.execute_unpack(*len)?;
+            }
+            Instruction::MakeStruct { name, fields } => {
+                self.execute_make_struct(name, fields)?;
+            }
+            Instruction::Assert { message, span } => {
+                if !self.pop_data()?.is_true()? {
+                    return Err(VMError::AssertionFailed {
+                        message: message.clone(),
+                        block: self.block,
+                        ip: self.ip,
+                        span: *span,
+                    });
                 }
             }
-            BlockExit::Return => {
-                self.ip = self
-                    .pop_address()?
-                    .get_num()?
-                    .to_usize()
-                    .ok_or(VMError::VoidValue)?;
+            Instruction::Commit => {
+                let value = self.pop_data()?;
+                value.flatten_into(&mut self.journal)?;
             }
-            _ => todo!(),
+            Instruction::Read { visibility } => {
+                let value = self.read_input(*visibility)?;
+                self.push_data(Value::Num {
+                    value: self.field.reduce(&value),
+                });
+            }
+            Instruction::RangeCheck { bits } => {
+                self.execute_range_check(*bits)?;
+            }
+            Instruction::GetField { name } => {
+                self.execute_get_field(name)?;
+            }
+            Instruction::Get { index } => {
+                self.execute_get(*index)?;
+            }
+            Instruction::Select => {
+                self.execute_select()?;
+            }
+            // The callee runs from `execute_block` so its steps land in the trace.
+            Instruction::Call { .. } => {}
         };
         Ok(ExecStep {
+            block: self.block,
             ip: self.ip,
-            op: StepFunction::from(*exit),
+            op: StepFunction::from(instruction.clone()),
         })
     }
 
+    fn read_operand(&self, operand: &Operand) -> Result<Value, VMError> {
+        match operand {
+            Operand::Constant(constant) => Ok(self.constant(constant)),
+            Operand::StackValue(depth) => self
+                .data
+                .len()
+                .checked_sub(depth + 1)
+                .map(|index| self.data[index].clone())
+                .ok_or(VMError::StackUnderflow),
+            Operand::Register(_) => Err(VMError::RegisterOperand),
+        }
+    }
+
+    /// Decides where control goes after a block of a function with `len` blocks.
+    fn execute_blockexit(&mut self, exit: &BlockExit, len: usize) -> Result<Next, VMError> {
+        let operands = exit.operands();
+        let values = operands
+            .iter()
+            .map(|operand| self.read_operand(operand))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Reading succeeded, so the stack is at least this deep.
+        let depth = operands
+            .iter()
+            .filter_map(|operand| match operand {
+                Operand::StackValue(depth) => Some(depth + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        self.data.truncate(self.data.len() - depth);
+        next(exit, values, len)
+    }
+
     fn execute_block(&mut self, block: &Block) -> Result<ExecTrace, VMError> {
         let mut log = vec![];
-        for instr in block.instructions.iter() {
+        for (ip, instr) in block.instructions.iter().enumerate() {
+            self.ip = ip;
             let result = self.execute_instruction(instr)?;
             log.push(result);
+            if let Instruction::Call { name } = instr {
+                let mut callee = self.execute_call(name)?;
+                log.append(&mut callee.log);
+            }
         }
-        let exit = self.execute_blockexit(&block.exit)?;
-        log.push(exit);
+        log.push(ExecStep {
+            block: self.block,
+            ip: block.instructions.len(),
+            op: StepFunction::from(block.exit.clone()),
+        });
         Ok(ExecTrace { log })
     }
 
-    pub fn execute(&mut self) -> Result<ExecTrace, VMError> {
+    /// Runs a function body from block 0, following each block's exit until
+    /// one returns. Returns the trace and the returned value.
+    fn execute_blocks(&mut self, blocks: &[Block]) -> Result<(ExecTrace, Option<Value>), VMError> {
         let mut trace = ExecTrace::new();
-        let program = self.program.clone();
-        for block in program.blocks.iter() {
+        self.block = 0;
+        loop {
+            let block = blocks
+                .get(self.block)
+                .ok_or(VMError::InvalidJumpTarget(self.block))?;
             let mut result = self.execute_block(block)?;
             trace.concat_trace(&mut result);
+            match self.execute_blockexit(&block.exit, blocks.len())? {
+                Next::Block(target, args) => {
+                    self.data.extend(args);
+                    self.block = target;
+                }
+                Next::Return(value) => {
+                    // Back to the caller's block. The outermost frame has no
+                    // return address, so the program ends there.
+                    if let Some(address) = self.address.pop() {
+                        self.block = address.get_num()?.to_usize().ok_or(VMError::VoidValue)?;
+                    }
+                    return Ok((trace, value));
+                }
+            }
         }
+    }
+
+    pub fn execute(&mut self) -> Result<ExecTrace, VMError> {
+        let program = Rc::clone(&self.program);
+        let (trace, value) = self.execute_blocks(&program.blocks)?;
+        self.data.extend(value);
         Ok(trace)
     }
 
@@ -194,10 +638,148 @@ impl VM {
     }
 }
 
+fn num_to_bool(value: BigInt) -> Result<bool, VMError> {
+    if value.is_zero() {
+        Ok(false)
+    } else if value.is_one() {
+        Ok(true)
+    } else {
+        Err(VMError::NotABoolean(value))
+    }
+}
+
+fn bool_to_num(value: bool) -> BigInt {
+    BigInt::from(value as u8)
+}
+
+/// Integer division of the representatives, for unsigned integer types.
+fn integer_divide(lhs: Value, rhs: Value) -> Result<Value, VMError> {
+    let rhs = rhs.get_num()?;
+    let lhs = lhs.get_num()?;
+    if rhs.is_zero() {
+        return Err(VMError::DivisionByZero);
+    }
+    Ok(Value::Num { value: lhs / rhs })
+}
+
+fn range_check(value: &Value, bits: u32) -> Result<(), VMError> {
+    let value = value.clone().get_num()?;
+    if value.sign() == Sign::Minus || value.bits() > u64::from(bits) {
+        return Err(VMError::RangeCheckFailed { value, bits });
+    }
+    Ok(())
+}
+
+fn unpack(tuple: Value, len: usize) -> Result<Vec<Value>, VMError> {
+    let elements = tuple.get_tuple()?;
+    if elements.len() != len {
+        return Err(VMError::UnpackMismatch {
+            expected: len,
+            found: elements.len(),
+        });
+    }
+    Ok(elements)
+}
+
+fn make_struct(name: &str, fields: &[String], values: Vec<Value>) -> Value {
+    Value::Struct {
+        name: name.to_string(),
+        fields: fields.iter().cloned().zip(values).collect(),
+    }
+}
+
+fn get_field(value: Value, field: &str) -> Result<Value, VMError> {
+    let (name, fields) = match value {
+        Value::Struct { name, fields } => (name, fields),
+        _ => return Err(VMError::NotAStruct),
+    };
+    fields
+        .into_iter()
+        .find(|(other, _)| other == field)
+        .map(|(_, value)| value)
+        .ok_or_else(|| VMError::UnknownField {
+            name,
+            field: field.to_string(),
+        })
+}
+
+fn get(array: Value, index: usize) -> Result<Value, VMError> {
+    let mut elements = array.get_array()?;
+    if index >= elements.len() {
+        return Err(VMError::IndexOutOfBounds {
+            index: index.into(),
+            len: elements.len(),
+        });
+    }
+    Ok(elements.swap_remove(index))
+}
+
+fn select(array: Value, index: Value) -> Result<Value, VMError> {
+    let index = index.get_num()?;
+    let mut elements = array.get_array()?;
+    match index.to_usize() {
+        Some(i) if i < elements.len() => Ok(elements.swap_remove(i)),
+        _ => Err(VMError::IndexOutOfBounds {
+            index,
+            len: elements.len(),
+        }),
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StackFrame {
+    pub func_name: String,
+    pub memory: Vec<Option<Value>>,
+    /// Only used by the register VM
+    pub registers: HashMap<Register, Value>,
+}
+
+impl StackFrame {
+    fn new(func_name: &str) -> Self {
+        StackFrame {
+            func_name: func_name.to_string(),
+            memory: vec![],
+            registers: HashMap::new(),
+        }
+    }
+}
+
 pub struct State {
     pub ip: Value,
 }
 
+/// Where execution continues after a block exit.
+#[derive(Debug, Clone, PartialEq)]
+enum Next {
+    /// Continue in the block with these arguments.
+    Block(BlockId, Vec<Value>),
+    /// The current function has returned.
+    Return(Option<Value>),
+}
+
+/// Follows `exit` in a function with `len` blocks, given the values of its
+/// operands in the order `BlockExit::operands` lists them.
+fn next(exit: &BlockExit, mut values: Vec<Value>, len: usize) -> Result<Next, VMError> {
+    let (target, args) = match exit {
+        BlockExit::Jump { target, .. } => (*target, values),
+        BlockExit::ConditionalJump { then, else_, .. } => {
+            let else_args = values.split_off(1 + then.args.len());
+            let then_args = values.split_off(1);
+            if values.remove(0).is_true()? {
+                (then.target, then_args)
+            } else {
+                (else_.target, else_args)
+            }
+        }
+        BlockExit::Return { .. } => return Ok(Next::Return(values.pop())),
+        BlockExit::Unreachable => return Err(VMError::Unreachable),
+    };
+    if target >= len {
+        return Err(VMError::InvalidJumpTarget(target));
+    }
+    Ok(Next::Block(target, args))
+}
+
 #[derive(Clone, Debug)]
 pub struct ExecTrace {
     pub log: Vec<ExecStep>,
@@ -222,6 +804,8 @@ impl ExecTrace {
 
 #[derive(Clone, Debug)]
 pub struct ExecStep {
+    pub block: BlockId,
+    /// Index of the instruction in `block`; the exit is one past the last.
     pub ip: usize,
     pub op: StepFunction,
 }
@@ -229,6 +813,7 @@ pub struct ExecStep {
 #[derive(Clone, Debug)]
 pub enum StepFunction {
     Instruction { instr: ir::Instruction },
+    Register { instr: ir::register::Instruction },
     Exit { exit: ir::BlockExit },
 }
 
@@ -238,16 +823,340 @@ impl From<ir::Instruction> for StepFunction {
     }
 }
 
+impl From<ir::register::Instruction> for StepFunction {
+    fn from(instr: ir::register::Instruction) -> Self {
+        StepFunction::Register { instr }
+    }
+}
+
 impl From<ir::BlockExit> for StepFunction {
     fn from(exit: ir::BlockExit) -> Self {
         StepFunction::Exit { exit }
     }
 }
 
-// TODO: Add function definition
-// #[derive(Debug, PartialEq, Clone)]
-// pub struct StackFrame<'i> {
-//     pub pc: ProgramCounter,
-//     pub func_name: String,
-//     pub func_def: &'i ir::FunctionDefinition,
-// }
+#[cfg(test)]
+mod tests {
+    use super::{StepFunction, VMError, Value, VM};
+    use crate::ast::ast::{BinaryOperator, UnaryOperator};
+    use crate::field::{BabyBear, Bn254, Field, Goldilocks};
+    use crate::ir::{
+        Block, BlockExit, Constant, FunctionDefinition, Instruction, JumpArg, Operand, Program,
+        Register,
+    };
+    use num_bigint::BigInt;
+
+    fn push(value: i64) -> Instruction {
+        Instruction::Push {
+            value: Constant::Num {
+                value: value.into(),
+            },
+        }
+    }
+
+    /// A program of one block that ends the program.
+    fn program(instructions: Vec<Instruction>) -> Program {
+        Program {
+            blocks: vec![Block {
+                instructions,
+                exit: BlockExit::Return { value: None },
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Runs `program(instructions)` and pops the value it leaves.
+    fn run(instructions: Vec<Instruction>) -> Result<Value, VMError> {
+        let mut vm = VM::new(program(instructions));
+        vm.execute()?;
+        vm.pop_data()
+    }
+
+    fn binop(op: BinaryOperator, lhs: i64, rhs: i64) -> Result<Value, VMError> {
+        run(vec![push(lhs), push(rhs), Instruction::BinOp { op }])
+    }
+
+    /// `value` as an element of the default field
+    fn num(value: i64) -> Value {
+        Value::Num {
+            value: Bn254.reduce(&value.into()),
+        }
+    }
+
+    #[test]
+    fn test_comparison_logical_bitwise() {
+        let cases = vec![
+            (BinaryOperator::LessThan, 2, 3, 1),
+            (BinaryOperator::GreaterThan, 2, 3, 0),
+            (BinaryOperator::LessEqual, 3, 3, 1),
+            (BinaryOperator::GreaterEqual, 2, 3, 0),
+            (BinaryOperator::Equal, 3, 3, 1),
+            (BinaryOperator::NotEqual, 3, 3, 0),
+            (BinaryOperator::And, 1, 0, 0),
+            (BinaryOperator::Or, 1, 0, 1),
+            (BinaryOperator::Xor, 0b1100, 0b1010, 0b0110),
+            (BinaryOperator::BitAnd, 0b1100, 0b1010, 0b1000),
+            (BinaryOperator::BitOr, 0b1100, 0b1010, 0b1110),
+            (BinaryOperator::ShiftLeft, 3, 4, 48),
+            (BinaryOperator::ShiftRight, 48, 4, 3),
+            (BinaryOperator::Modulo, 17, 5, 2),
+            (BinaryOperator::Plus, 2, 5, 7),
+            // The right operand is on top of the stack.
+            (BinaryOperator::Minus, 2, 5, -3),
+            (BinaryOperator::Multiply, 2, 5, 10),
+            (BinaryOperator::Divide, 15, 5, 3),
+        ];
+
+        for (op, lhs, rhs, expected) in cases {
+            assert_eq!(binop(op.clone(), lhs, rhs), Ok(num(expected)), "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn test_binop_errors() {
+        assert_eq!(
+            binop(BinaryOperator::Modulo, 1, 0),
+            Err(VMError::DivisionByZero)
+        );
+        assert_eq!(
+            binop(BinaryOperator::ShiftLeft, 1, -1),
+            Err(VMError::InvalidShift(Bn254.reduce(&BigInt::from(-1))))
+        );
+        // A prover-chosen amount must not build a huge number before reduction.
+        assert_eq!(
+            binop(BinaryOperator::ShiftLeft, 1, 1 << 40),
+            Err(VMError::InvalidShift(BigInt::from(1u64 << 40)))
+        );
+        assert_eq!(
+            binop(BinaryOperator::ShiftRight, 1, 254),
+            Err(VMError::InvalidShift(BigInt::from(254)))
+        );
+        assert_eq!(
+            binop(BinaryOperator::ShiftLeft, 1, 253),
+            Ok(Value::Num {
+                value: BigInt::from(1) << 253
+            })
+        );
+        assert_eq!(
+            binop(BinaryOperator::Divide, 1, 0),
+            Err(VMError::DivisionByZero)
+        );
+        assert_eq!(
+            binop(BinaryOperator::And, 1, 2),
+            Err(VMError::NotABoolean(BigInt::from(2)))
+        );
+
+        let op = Instruction::BinOp {
+            op: BinaryOperator::Plus,
+        };
+        let unit = Instruction::Push {
+            value: Constant::Unit,
+        };
+        let array = Instruction::MakeArray { len: 1 };
+        assert_eq!(
+            run(vec![push(1), unit, op.clone()]),
+            Err(VMError::VoidValue)
+        );
+        assert_eq!(
+            run(vec![push(1), array, push(1), op]),
+            Err(VMError::NotANumber)
+        );
+    }
+
+    #[test]
+    fn test_field_arithmetic() {
+        let op = |op| Instruction::BinOp { op };
+        // (-1 - 3) * (1 / 2 * 4) = -8
+        let program = program(vec![
+            push(-1),
+            push(3),
+            op(BinaryOperator::Minus),
+            push(1),
+            push(2),
+            op(BinaryOperator::Divide),
+            push(4),
+            op(BinaryOperator::Multiply),
+            op(BinaryOperator::Multiply),
+        ]);
+
+        let vms = [
+            VM::new(program.clone()).with_field(Bn254),
+            VM::new(program.clone()).with_field(Goldilocks),
+            VM::new(program).with_field(BabyBear),
+        ];
+        for mut vm in vms {
+            vm.execute().unwrap();
+            let p = vm.field().modulus();
+            assert_eq!(
+                vm.data,
+                vec![Value::Num { value: p - 8 }],
+                "{}",
+                vm.field().name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        let unop = |op, value: i64| run(vec![push(value), Instruction::UnaryOp { op }]);
+
+        assert_eq!(unop(UnaryOperator::Minus, 5), Ok(num(-5)));
+        assert_eq!(unop(UnaryOperator::Not, 0), Ok(num(1)));
+        assert_eq!(unop(UnaryOperator::Not, 1), Ok(num(0)));
+        assert_eq!(
+            unop(UnaryOperator::Not, 7),
+            Err(VMError::NotABoolean(BigInt::from(7)))
+        );
+    }
+
+    #[test]
+    fn test_array_access() {
+        let array = vec![push(10), push(20), Instruction::MakeArray { len: 2 }];
+
+        let get = [array.clone(), vec![Instruction::Get { index: 1 }]].concat();
+        assert_eq!(run(get), Ok(num(20)));
+        let select = [array.clone(), vec![push(0), Instruction::Select]].concat();
+        assert_eq!(run(select), Ok(num(10)));
+
+        let out_of_bounds = [array.clone(), vec![push(2), Instruction::Select]].concat();
+        assert_eq!(
+            run(out_of_bounds),
+            Err(VMError::IndexOutOfBounds {
+                index: BigInt::from(2),
+                len: 2
+            })
+        );
+        let add = [
+            array,
+            vec![
+                push(1),
+                Instruction::BinOp {
+                    op: BinaryOperator::Plus,
+                },
+            ],
+        ]
+        .concat();
+        assert_eq!(run(add), Err(VMError::NotANumber));
+    }
+
+    #[test]
+    fn test_unpack_mismatch() {
+        let instructions = vec![
+            push(1),
+            push(2),
+            Instruction::MakeTuple { len: 2 },
+            Instruction::Unpack { len: 3 },
+        ];
+        assert_eq!(
+            run(instructions),
+            Err(VMError::UnpackMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let block = |instructions, exit| Block { instructions, exit };
+        let jump = |target, args| BlockExit::Jump { target, args };
+        let blocks = |condition: i64| {
+            vec![
+                block(
+                    vec![push(7), push(condition)],
+                    BlockExit::ConditionalJump {
+                        cond: Operand::StackValue(0),
+                        then: JumpArg {
+                            target: 1,
+                            args: vec![Operand::StackValue(1)],
+                        },
+                        else_: JumpArg {
+                            target: 2,
+                            args: vec![Operand::Constant(Constant::Num { value: 20.into() })],
+                        },
+                    },
+                ),
+                block(
+                    vec![
+                        push(3),
+                        Instruction::BinOp {
+                            op: BinaryOperator::Multiply,
+                        },
+                    ],
+                    jump(3, vec![Operand::StackValue(0)]),
+                ),
+                block(vec![], jump(3, vec![])),
+                block(
+                    vec![],
+                    BlockExit::Return {
+                        value: Some(Operand::StackValue(0)),
+                    },
+                ),
+            ]
+        };
+        assert_eq!(blocks(1)[0].exit.successors(), vec![1, 2]);
+        assert_eq!(blocks(1)[3].exit.successors(), vec![]);
+
+        // Like `run`, but for several blocks, and also reports the exits taken.
+        let run_blocks = |blocks| {
+            let mut vm = VM::new(Program {
+                blocks,
+                ..Default::default()
+            });
+            let trace = vm.execute()?;
+            let exits: Vec<_> = trace
+                .log
+                .iter()
+                .filter(|step| matches!(step.op, StepFunction::Exit { .. }))
+                .map(|step| step.block)
+                .collect();
+            Ok::<_, VMError>((vm.data, exits))
+        };
+        // The condition and the 7 are consumed by the exit, whichever way it goes.
+        assert_eq!(run_blocks(blocks(1)), Ok((vec![num(21)], vec![0, 1, 3])));
+        assert_eq!(run_blocks(blocks(0)), Ok((vec![num(20)], vec![0, 2, 3])));
+        assert_eq!(
+            run_blocks(blocks(2)),
+            Err(VMError::NotABoolean(BigInt::from(2)))
+        );
+
+        assert_eq!(
+            run_blocks(vec![block(vec![], jump(1, vec![]))]),
+            Err(VMError::InvalidJumpTarget(1))
+        );
+        assert_eq!(
+            run_blocks(vec![block(vec![], jump(0, vec![Operand::StackValue(0)]))]),
+            Err(VMError::StackUnderflow)
+        );
+        let register = Operand::Register(Register::Zero);
+        assert_eq!(
+            run_blocks(vec![block(vec![], jump(0, vec![register]))]),
+            Err(VMError::RegisterOperand)
+        );
+    }
+    #[test]
+    fn test_call_drops_callee_stack() {
+        // `f` takes one argument but leaves two more values under its result.
+        let f = FunctionDefinition {
+            params: 1,
+            blocks: vec![Block {
+                instructions: vec![push(7), push(8), push(9)],
+                exit: BlockExit::Return {
+                    value: Some(Operand::StackValue(0)),
+                },
+            }],
+        };
+        let mut program = program(vec![
+            push(1),
+            push(2),
+            Instruction::Call {
+                name: "f".to_string(),
+            },
+        ]);
+        program.functions.insert("f".to_string(), f);
+
+        let mut vm = VM::new(program);
+        vm.execute().unwrap();
+        assert_eq!(vm.data, vec![num(1), num(9)]);
+    }
+}
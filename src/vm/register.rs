@@ -0,0 +1,343 @@
+use num_traits::ToPrimitive;
+
+use super::{
+    get, get_field, integer_divide, make_struct, next, range_check, select, unpack, ExecStep,
+    ExecTrace, Next, StackFrame, StepFunction, VMError, Value, MAX_CALL_DEPTH, VM,
+};
+use crate::ir::{
+    register::{self, param, Block, Instruction},
+    Operand, Register,
+};
+
+impl VM {
+    /// Runs the program on a register machine instead: it is translated to the
+    /// register IR first. Inputs, the journal and memory work as in `execute`,
+    /// and a top-level `return` leaves its value on the data stack in the same
+    /// way, so the two modes can be compared run for run.
+    pub fn execute_registers(&mut self) -> Result<ExecTrace, VMError> {
+        let program = register::translate(&self.program)?;
+        let (trace, value) = self.execute_register_blocks(&program, &program.blocks, vec![])?;
+        self.data.extend(value);
+        Ok(trace)
+    }
+
+    /// Runs a function body from block 0 with `args` as its parameters.
+    /// Returns the trace and the returned value.
+    fn execute_register_blocks(
+        &mut self,
+        program: &register::Program,
+        blocks: &[Block],
+        args: Vec<Value>,
+    ) -> Result<(ExecTrace, Option<Value>), VMError> {
+        let mut trace = ExecTrace::new();
+        self.block = 0;
+        self.set_params(args);
+        loop {
+            let block = blocks
+                .get(self.block)
+                .ok_or(VMError::InvalidJumpTarget(self.block))?;
+            for (ip, instruction) in block.instructions.iter().enumerate() {
+                self.ip = ip;
+                trace.log.push(ExecStep {
+                    block: self.block,
+                    ip,
+                    op: StepFunction::from(instruction.clone()),
+                });
+                if let Instruction::Call { name, dst, args } = instruction {
+                    let mut callee = self.execute_register_call(program, name, *dst, args)?;
+                    trace.concat_trace(&mut callee);
+                } else {
+                    self.execute_register_instruction(instruction)?;
+                }
+            }
+            trace.log.push(ExecStep {
+                block: self.block,
+                ip: block.instructions.len(),
+                op: StepFunction::from(block.exit.clone()),
+            });
+
+            let values = block
+                .exit
+                .operands()
+                .into_iter()
+                .map(|operand| self.read_register_operand(operand))
+                .collect::<Result<Vec<_>, _>>()?;
+            match next(&block.exit, values, blocks.len())? {
+                Next::Block(target, args) => {
+                    self.set_params(args);
+                    self.block = target;
+                }
+                Next::Return(value) => return Ok((trace, value)),
+            }
+        }
+    }
+
+    fn execute_register_call(
+        &mut self,
+        program: &register::Program,
+        name: &str,
+        dst: Register,
+        args: &[Operand],
+    ) -> Result<ExecTrace, VMError> {
+        let function = program
+            .functions
+            .get(name)
+            .ok_or_else(|| VMError::UndefinedFunction(name.to_string()))?;
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(VMError::CallDepthExceeded);
+        }
+        let args = self.read_register_operands(args)?;
+
+        let (block, ip) = (self.block, self.ip);
+        self.frames.push(StackFrame::new(name));
+        let (trace, value) = self.execute_register_blocks(program, &function.blocks, args)?;
+        self.frames.pop();
+        (self.block, self.ip) = (block, ip);
+
+        self.write_register(dst, value.unwrap_or(Value::Unit));
+        Ok(trace)
+    }
+
+    fn execute_register_instruction(&mut self, instruction: &Instruction) -> Result<(), VMError> {
+        match instruction {
+            Instruction::BinOp { op, dst, lhs, rhs } => {
+                let lhs = self.read_register_operand(lhs)?;
+                let rhs = self.read_register_operand(rhs)?;
+                let value = self.binop(op, lhs, rhs)?;
+                self.write_register(*dst, value);
+            }
+            Instruction::UnaryOp { op, dst, src } => {
+                let operand = self.read_register_operand(src)?;
+                let value = self.unop(op, operand)?;
+                self.write_register(*dst, value);
+            }
+            Instruction::IntegerDivide { dst, lhs, rhs } => {
+                let lhs = self.read_register_operand(lhs)?;
+                let rhs = self.read_register_operand(rhs)?;
+                self.write_register(*dst, integer_divide(lhs, rhs)?);
+            }
+            Instruction::Load { dst, address } => {
+                let address = self.read_address(address)?;
+                let value = self.load(address)?;
+                self.write_register(*dst, value);
+            }
+            Instruction::Store { address, src } => {
+                let address = self.read_address(address)?;
+                let value = self.read_register_operand(src)?;
+                self.store(address, value);
+            }
+            Instruction::MakeArray { dst, elements } => {
+                let elements = self.read_register_operands(elements)?;
+                self.write_register(*dst, Value::Array { elements });
+            }
+            Instruction::Get { dst, src, index } => {
+                let array = self.read_register_operand(src)?;
+                self.write_register(*dst, get(array, *index)?);
+            }
+            Instruction::Select { dst, array, index } => {
+                let array = self.read_register_operand(array)?;
+                let index = self.read_register_operand(index)?;
+                self.write_register(*dst, select(array, index)?);
+            }
+            Instruction::MakeTuple { dst, elements } => {
+                let elements = self.read_register_operands(elements)?;
+                self.write_register(*dst, Value::Tuple { elements });
+            }
+            Instruction::Unpack { dsts, src } => {
+                let tuple = self.read_register_operand(src)?;
+                for (dst, value) in dsts.iter().zip(unpack(tuple, dsts.len())?) {
+                    self.write_register(*dst, value);
+                }
+            }
+            Instruction::MakeStruct {
+                dst,
+                name,
+                fields,
+                values,
+            } => {
+                let values = self.read_register_operands(values)?;
+                self.write_register(*dst, make_struct(name, fields, values));
+            }
+            Instruction::Assert {
+                cond,
+                message,
+                span,
+            } => {
+                if !self.read_register_operand(cond)?.is_true()? {
+                    return Err(VMError::AssertionFailed {
+                        message: message.clone(),
+                        block: self.block,
+                        ip: self.ip,
+                        span: *span,
+                    });
+                }
+            }
+            Instruction::Commit { src } => {
+                let value = self.read_register_operand(src)?;
+                value.flatten_into(&mut self.journal)?;
+            }
+            Instruction::Read { dst, visibility } => {
+                let value = self.read_input(*visibility)?;
+                let value = Value::Num {
+                    value: self.field.reduce(&value),
+                };
+                self.write_register(*dst, value);
+            }
+            Instruction::RangeCheck { src, bits } => {
+                range_check(&self.read_register_operand(src)?, *bits)?;
+            }
+            Instruction::GetField { dst, src, name } => {
+                let value = self.read_register_operand(src)?;
+                self.write_register(*dst, get_field(value, name)?);
+            }
+            // The callee runs from `execute_register_blocks` so its steps land
+            // in the trace.
+            Instruction::Call { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn read_register_operand(&mut self, operand: &Operand) -> Result<Value, VMError> {
+        match operand {
+            Operand::Constant(constant) => Ok(self.constant(constant)),
+            Operand::Register(Register::Zero) => Ok(Value::Num { value: 0.into() }),
+            Operand::Register(register) => self
+                .frame()
+                .registers
+                .get(register)
+                .cloned()
+                .ok_or(VMError::UninitializedRegister(*register)),
+            Operand::StackValue(_) => Err(VMError::StackOperand),
+        }
+    }
+
+    fn read_register_operands(&mut self, operands: &[Operand]) -> Result<Vec<Value>, VMError> {
+        operands
+            .iter()
+            .map(|operand| self.read_register_operand(operand))
+            .collect()
+    }
+
+    fn read_address(&mut self, operand: &Operand) -> Result<usize, VMError> {
+        self.read_register_operand(operand)?
+            .get_num()?
+            .to_usize()
+            .ok_or(VMError::VoidValue)
+    }
+
+    /// Writes to `Zero` are discarded.
+    fn write_register(&mut self, register: Register, value: Value) {
+        if register != Register::Zero {
+            self.frame().registers.insert(register, value);
+        }
+    }
+
+    /// Sets the parameters of the block being entered.
+    fn set_params(&mut self, args: Vec<Value>) {
+        for (index, value) in args.into_iter().enumerate() {
+            self.write_register(param(index), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use crate::compiler::compile_source;
+    use crate::ir::{self, register::Instruction};
+    use crate::vm::{StepFunction, VMError, VM};
+
+    #[test]
+    fn test_register_mode_matches_stack_mode() {
+        let programs: [(&str, Vec<i64>); 5] = [
+            ("private x: field; return x * 3 + 1;", vec![4]),
+            (
+                "function pick(c: bool, a, b) { if c { return a; } return b; }
+                private c: bool;
+                let y = 5 + if c { pick(c, 1, 2) } else { pick(c, 3, 4) };
+                commit y;
+                return (y, pick(!c, y, 0));",
+                vec![0],
+            ),
+            (
+                "struct P { x: u8, y: u8 }
+                private i: field;
+                let xs = [10, 20, 30];
+                let p = P { x: 7, y: 2 };
+                let (a, b) = (xs[i], p.x / p.y);
+                return (a, b);",
+                vec![2],
+            ),
+            (
+                "let mut acc = 0;
+                for i in 0..4 { if i % 2 == 0 { acc += i; } else { acc -= 1; } }
+                return acc;",
+                vec![],
+            ),
+            ("let x = 1; x + 2;", vec![]),
+        ];
+
+        for (input, private) in programs {
+            let program = compile_source(input);
+            let private: Vec<BigInt> = private.into_iter().map(Into::into).collect();
+            let mut stack = VM::new(program.clone()).with_inputs(vec![], private.clone());
+            let stack_steps = stack.execute().unwrap().log.len();
+            let mut registers = VM::new(program).with_inputs(vec![], private);
+            let register_steps = registers.execute_registers().unwrap().log.len();
+
+            assert_eq!(stack.data, registers.data, "{}", input);
+            assert_eq!(stack.journal, registers.journal, "{}", input);
+            // Constants, `Dup` and `Swap` cost nothing on the register machine.
+            assert!(register_steps < stack_steps, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_register_mode_errors() {
+        let input = "private x: field; assert(x == 3, \"x must be 3\"); return x;";
+        let mut vm = VM::new(compile_source(input)).with_inputs(vec![], vec![4.into()]);
+        let Err(VMError::AssertionFailed {
+            message,
+            block,
+            ip,
+            span,
+        }) = vm.execute_registers()
+        else {
+            panic!("expected an assertion failure");
+        };
+        assert_eq!(message.as_deref(), Some("x must be 3"));
+        assert_eq!(
+            &input[span.start..span.end],
+            "assert(x == 3, \"x must be 3\");"
+        );
+        let program = ir::register::translate(&vm.program).unwrap();
+        assert!(matches!(
+            program.blocks[block].instructions[ip],
+            Instruction::Assert { .. }
+        ));
+
+        let input = "private s: field; return 1 << s;";
+        for registers in [false, true] {
+            let mut vm =
+                VM::new(compile_source(input)).with_inputs(vec![], vec![(1u64 << 40).into()]);
+            let result = if registers {
+                vm.execute_registers()
+            } else {
+                vm.execute()
+            };
+            assert_eq!(
+                result.err(),
+                Some(VMError::InvalidShift(BigInt::from(1u64 << 40)))
+            );
+        }
+
+        // Only register instructions are traced.
+        let mut vm = VM::new(compile_source("let x = 1; commit x;"));
+        let trace = vm.execute_registers().unwrap();
+        assert!(trace
+            .log
+            .iter()
+            .all(|step| !matches!(step.op, StepFunction::Instruction { .. })));
+    }
+}